@@ -0,0 +1,113 @@
+use machine::TIMER_FREQ;
+
+pub const AUDIO_SAMPLE_RATE: u32 = 44_100;
+/// The VIP's beeper sounded at roughly this pitch.
+pub const DEFAULT_TONE_FREQ: f64 = 440.0;
+pub const DEFAULT_VOLUME: f64 = 0.25;
+
+/// XO-CHIP plays its 128-bit samples at this many bits per second when the
+/// pitch register holds its default of 64.
+pub const PATTERN_BASE_RATE: f64 = 4000.0;
+
+/// Tone generator, sounded while the sound timer is non-zero. Plays a square
+/// wave, or an XO-CHIP sample once a program has loaded one. Produces signed
+/// 16-bit mono samples one 60 Hz frame at a time, keeping the phase across
+/// frames so a held tone does not click at frame boundaries.
+pub struct SquareWave {
+    pub frequency: f64,
+    /// Amplitude between 0 and 1.
+    pub volume: f64,
+    pub sample_rate: u32,
+    phase: f64,
+    /// Samples owed to the next frame when the rate does not divide evenly.
+    remainder: u32,
+    buffer: Vec<i16>,
+}
+
+impl SquareWave {
+    pub fn new(sample_rate: u32) -> Self {
+        SquareWave {
+            frequency: DEFAULT_TONE_FREQ,
+            volume: DEFAULT_VOLUME,
+            sample_rate,
+            phase: 0.0,
+            remainder: 0,
+            buffer: Vec::new(),
+        }
+    }
+
+    /// Generates the samples for one frame, silent unless `on`. With a
+    /// `pattern`, its bits are played from the most significant of the first
+    /// byte on, at `PATTERN_BASE_RATE` scaled by an octave for every 48 steps
+    /// `pitch` is away from 64; otherwise the tone is a square wave at
+    /// `frequency`.
+    pub fn frame(&mut self, on: bool, pattern: Option<&[u8; 16]>, pitch: u8) -> &[i16] {
+        let total = self.sample_rate + self.remainder;
+        let count = (total / TIMER_FREQ as u32) as usize;
+        self.remainder = total % TIMER_FREQ as u32;
+
+        let amplitude = (self.volume.clamp(0.0, 1.0) * i16::MAX as f64) as i16;
+        // The phase runs over one whole pattern, or one cycle of the wave.
+        let step = match pattern {
+            Some(_) => PATTERN_BASE_RATE * 2f64.powf((pitch as f64 - 64.0) / 48.0) / 128.0,
+            None => self.frequency,
+        } / self.sample_rate as f64;
+        self.buffer.clear();
+        for _ in 0..count {
+            let high = match pattern {
+                Some(pattern) => {
+                    let bit = (self.phase * 128.0) as usize;
+                    pattern[bit / 8] & (0x80 >> (bit % 8)) != 0
+                }
+                None => self.phase < 0.5,
+            };
+            let sample = match (on, high) {
+                (false, _) => 0,
+                (true, true) => amplitude,
+                (true, false) => -amplitude,
+            };
+            self.buffer.push(sample);
+            self.phase = (self.phase + step).fract();
+        }
+        &self.buffer
+    }
+}
+
+impl Default for SquareWave {
+    fn default() -> Self {
+        Self::new(AUDIO_SAMPLE_RATE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plays_square_waves() {
+        let mut synth = SquareWave::new(8000);
+        synth.frequency = 100.0;
+        let samples = synth.frame(true, None, 64).to_vec();
+        assert_eq!(samples.len(), 133);
+        assert!(samples[..40].iter().all(|&sample| sample > 0));
+        assert!(samples[40..80].iter().all(|&sample| sample < 0));
+        assert!(synth.frame(false, None, 64).iter().all(|&sample| sample == 0));
+    }
+
+    #[test]
+    fn plays_xo_chip_patterns() {
+        // Half the pattern high, which at the base rate of 4000 bits per
+        // second lasts 16 ms.
+        let mut pattern = [0; 16];
+        pattern[..8].copy_from_slice(&[0xFF; 8]);
+        let samples = SquareWave::new(AUDIO_SAMPLE_RATE).frame(true, Some(&pattern), 64).to_vec();
+        assert!(samples[..705].iter().all(|&sample| sample > 0));
+        assert!(samples[706..].iter().all(|&sample| sample < 0));
+
+        // An octave up the whole pattern plays in 16 ms.
+        let samples = SquareWave::new(AUDIO_SAMPLE_RATE).frame(true, Some(&pattern), 64 + 48).to_vec();
+        assert!(samples[..352].iter().all(|&sample| sample > 0));
+        assert!(samples[353..705].iter().all(|&sample| sample < 0));
+        assert!(samples[706..].iter().all(|&sample| sample > 0));
+    }
+}
@@ -0,0 +1,52 @@
+extern crate chip8;
+use std::env;
+use std::process;
+use chip8::{Options, Phosphor, Tui, USAGE};
+
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    let options = match Options::parse(&args) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{}", message);
+            eprintln!("{}", USAGE.replacen("chip8", "chip8-tui", 1));
+            process::exit(1);
+        }
+    };
+
+    let audio = match options.audio_driver() {
+        Ok(audio) => audio,
+        Err(message) => {
+            eprintln!("{}", message);
+            process::exit(1);
+        }
+    };
+
+    let mut tui = Tui::new(audio, options.quirks);
+    let config = options.setup(&mut tui.machine).and_then(|()| options.config(&tui.machine.rom_name));
+    let config = config.and_then(|config| {
+        options.start_video(&mut tui.machine, &config.palette.unwrap_or_default())?;
+        Ok(config)
+    });
+    match config {
+        Ok(config) => {
+            tui.bindings = config.bindings;
+            tui.display.palette = config.palette.unwrap_or_default();
+            tui.display.phosphor = Phosphor::new(config.phosphor.unwrap_or_default());
+        }
+        Err(message) => {
+            eprintln!("{}", message);
+            process::exit(1);
+        }
+    }
+
+    if let Err(error) = tui.boot() {
+        eprintln!("{}", error);
+        process::exit(1);
+    }
+
+    options.save_video(&mut tui.machine);
+    options.save_movie(&mut tui.machine);
+}
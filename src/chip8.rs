@@ -1,56 +1,154 @@
-use std::sync::{Arc, Mutex};
-use hardware::{AudioDriver, KeyboardDriver};
-use memory::Memory;
-use debugger::{Debugger, DebugMode};
-use cpu::Cpu;
+use hardware::{AudioDriver, DisplayDriver, KeyboardDriver};
+use machine::{Machine, CHIP8_WIDTH, CHIP8_HEIGHT, MAX_INSTRUCTIONS_PER_FRAME, TIMER_FREQ};
+use std::io::{self, Write};
+use debugger::{spawn_console, DebugCommand, DEBUG_PROMPT};
+use quirks::Quirks;
+use error::Chip8Error;
+use rewind::Rewind;
+use config::{Hotkey, KeyBindings};
+use palette::Palette;
+use phosphor::Phosphor;
+use viewport::{ScaleMode, Viewport};
 use glutin_window::GlutinWindow as Window;
-use piston::window::WindowSettings;
+use piston::window::{AdvancedWindow, Window as PistonWindow, WindowSettings};
 use piston::event_loop::{Events, EventSettings, EventLoop};
-use opengl_graphics::{OpenGL, GlGraphics};
-use piston::input::{RenderEvent, RenderArgs, Button, ButtonState, ButtonEvent, Key, UpdateEvent}; // self, Button, Event, Input, 
-use graphics::{self, Transformed};
-
-pub const FONT_SET: [u8; 80] = 
-[
-    0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
-    0x20, 0x60, 0x20, 0x20, 0x70, // 1
-    0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
-    0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
-    0x90, 0x90, 0xF0, 0x10, 0x10, // 4
-    0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
-    0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
-    0xF0, 0x10, 0x20, 0x40, 0x40, // 7
-    0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
-    0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
-    0xF0, 0x90, 0xF0, 0x90, 0x90, // A
-    0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
-    0xF0, 0x80, 0x80, 0x80, 0xF0, // C
-    0xE0, 0x90, 0x90, 0x90, 0xE0, // D
-    0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
-    0xF0, 0x80, 0xF0, 0x80, 0x80, // F
-];
-
-pub const OPCODE_SIZE: u16 = 2;
-pub const CHIP8_WIDTH: usize = 64;
-pub const CHIP8_HEIGHT: usize = 32;
-pub const CLOCK_FREQ: u64 = 512;
+use opengl_graphics::{OpenGL, GlGraphics, Texture, TextureSettings, Filter, Format, CreateTexture, UpdateTexture};
+use piston::input::{RenderEvent, RenderArgs, Button, ButtonState, ButtonEvent, FocusEvent, Key, UpdateEvent}; // self, Button, Event, Input, 
+use graphics::{self, ImageSize};
+
 pub const SCALE: usize = 10;
-pub const BACKGROUND: [f32; 4] = [0.3, 0.3, 0.3, 1.0];
-pub const FOREGROUND: [f32; 4] = [1.0, 0.13, 0.43, 1.0];
+pub const TURBO_SPEED: f64 = 4.0;
+pub const SLOW_MOTION_SPEED: f64 = 0.25;
+
+/// Black bars around the screen when the window has a different shape.
+const LETTERBOX: [f32; 4] = [0.0, 0.0, 0.0, 1.0];
+
+/// Draws the last presented framebuffer with OpenGL, scaled to the window and
+/// centred in it. The framebuffer is uploaded as one texture, and only when
+/// it changed; `render` draws nothing unless the texture or the window size
+/// did, so an idle game costs next to nothing.
+pub struct GlDisplay {
+    pub gfx: GlGraphics,
+    pub palette: Palette,
+    pub phosphor: Phosphor,
+    pub scaling: ScaleMode,
+    width: usize,
+    height: usize,
+    pixels: Vec<u8>,
+    texture: Option<Texture>,
+    /// Whether `pixels` changed since they were last uploaded.
+    changed: bool,
+    /// Window size of the last render, if it is still on screen.
+    drawn: Option<[u32; 2]>,
+}
+
+impl GlDisplay {
+    pub fn new(gfx: GlGraphics) -> Self {
+        GlDisplay {
+            gfx,
+            palette: Palette::default(),
+            phosphor: Phosphor::default(),
+            scaling: ScaleMode::default(),
+            width: CHIP8_WIDTH,
+            height: CHIP8_HEIGHT,
+            pixels: Palette::default().color(0).repeat(CHIP8_WIDTH * CHIP8_HEIGHT),
+            texture: None,
+            changed: true,
+            drawn: None,
+        }
+    }
 
+    /// Forces the next `render` to draw, for when the window contents may
+    /// have been lost.
+    pub fn invalidate(&mut self) {
+        self.drawn = None;
+    }
+
+    /// Draws the screen if anything changed since the last call, returning
+    /// whether it did and the buffers need swapping.
+    pub fn render(&mut self, args: &RenderArgs) -> bool {
+        let size = [args.width, args.height];
+        if !self.changed && self.drawn == Some(size) {
+            return false;
+        }
+        self.upload();
+
+        let (width, height) = (self.width as f64, self.height as f64);
+        let view = Viewport::fit(self.width, self.height, args.width as f64, args.height as f64, self.scaling);
+        let screen = graphics::Image::new().rect([view.x, view.y, view.pixel_width * width, view.pixel_height * height]);
+        let texture = match self.texture {
+            Some(ref texture) => texture,
+            None => return false,
+        };
+
+        self.gfx.draw(args.viewport(), |c, gfx| {
+            graphics::clear(LETTERBOX, gfx);
+            screen.draw(texture, &c.draw_state, c.transform, gfx);
+        });
+        self.drawn = Some(size);
+        true
+    }
+
+    /// Copies `pixels` into the texture, recreating it at a new resolution.
+    fn upload(&mut self) {
+        if !self.changed {
+            return;
+        }
+        self.changed = false;
+        let size = [self.width as u32, self.height as u32];
+        match self.texture {
+            Some(ref mut texture) if texture.get_size() == (size[0], size[1]) => {
+                if let Err(error) = UpdateTexture::update(texture, &mut (), Format::Rgba8, &self.pixels, [0, 0], size) {
+                    eprintln!("{}", error);
+                }
+            }
+            _ => {
+                let settings = TextureSettings::new().filter(Filter::Nearest);
+                self.texture = match CreateTexture::create(&mut (), Format::Rgba8, &self.pixels, size, &settings) {
+                    Ok(texture) => Some(texture),
+                    Err(error) => {
+                        eprintln!("{}", error);
+                        None
+                    }
+                };
+            }
+        }
+    }
+}
+
+impl DisplayDriver for GlDisplay {
+    fn refresh(&mut self, vram: &[Vec<u8>], dirty: &[bool]) {
+        if !self.phosphor.update(vram, dirty, &self.palette) {
+            return;
+        }
+        self.width = vram[0].len();
+        self.height = vram.len();
+        self.pixels.clear();
+        for color in self.phosphor.colors() {
+            self.pixels.extend_from_slice(color);
+        }
+        self.changed = true;
+    }
+}
+
+/// Piston frontend driving a `Machine` in an OpenGL window.
 pub struct Chip8<A, K>
     where
         A: AudioDriver + Sync + Send,
         K: KeyboardDriver + Sync + Send,
 {
-    pub memory: Arc<Mutex<Memory>>,
-    pub cpu: Arc<Mutex<Cpu>>,
-    pub keyboard: K,
-    pub audio: A,
-    pub clock: u64,
-    pub gfx: GlGraphics,
+    pub machine: Machine<A, K>,
+    pub display: GlDisplay,
     pub window: Window,
-    pub debugger: Debugger,
+    pub turbo: bool,
+    pub slow_motion: bool,
+    pub frame_budget: f64,
+    pub rewind: Rewind,
+    pub rewinding: bool,
+    pub bindings: KeyBindings,
+    pub fullscreen: bool,
+    /// Repeated when an empty line is entered at the console, as in gdb.
+    pub last_command: Option<DebugCommand>,
 }
 
 impl<A: 'static, K: 'static> Chip8<A, K>
@@ -58,10 +156,8 @@ impl<A: 'static, K: 'static> Chip8<A, K>
         A: AudioDriver + Sync + Send,
         K: KeyboardDriver + Sync + Send,
 {
-    pub fn new(audio: A, keyboard: K) -> Self {
-        let memory = Arc::new(Mutex::new(Memory::new()));
-        let cpu = Arc::new(Mutex::new(Cpu::new(memory.clone())));
-        let clock = 1000 / CLOCK_FREQ;
+    pub fn new(audio: A, keyboard: K, quirks: Quirks) -> Self {
+        let machine = Machine::new(audio, keyboard, quirks);
         let opengl = OpenGL::V3_2;
         let width = CHIP8_WIDTH * SCALE;
         let height = CHIP8_HEIGHT * SCALE;
@@ -69,114 +165,264 @@ impl<A: 'static, K: 'static> Chip8<A, K>
         let window: Window = WindowSettings::new("Chip8", [width as u32, height as u32])
             .opengl(opengl)
             .exit_on_esc(true)
+            .resizable(true)
             .vsync(true)
             .build()
             .unwrap();
 
-        let debugger = Debugger::new(cpu.clone(), memory.clone());
-        let gfx = GlGraphics::new(opengl);
+        let display = GlDisplay::new(GlGraphics::new(opengl));
 
         Chip8 {
-            memory,
-            cpu,
-            audio,
-            keyboard,
-            clock,
-            gfx,
+            machine,
+            display,
             window,
-            debugger,
+            turbo: false,
+            slow_motion: false,
+            frame_budget: 0.0,
+            rewind: Rewind::default(),
+            rewinding: false,
+            bindings: KeyBindings::default(),
+            fullscreen: false,
+            last_command: None,
         }
     }
 
-    pub fn load_program(&mut self, program: &[u8]) {
-        if program.len() > (4096 - 512) {
-            panic!("This program is too big to run in this interpreter");
+    /// Runs one machine frame and records it in the rewind buffer. Faults stop
+    /// the emulation and pause it in the debugger.
+    pub fn run_frame(&mut self) {
+        match self.machine.frame() {
+            Ok(()) => self.rewind.push(self.machine.save_state()),
+            Err(error) => self.machine.debugger.pause(error.to_string()),
         }
-        self.debugger.program = program.to_vec();
-        let mut memory = self.memory.lock().unwrap();
-        for addr in 0..program.len() {
-            let offset = 0x200;
-            memory.ram[addr + offset] = program[addr];
+    }
+
+    /// Runs a line typed at the debugger console. An empty line repeats the
+    /// previous command.
+    pub fn console_command(&mut self, line: &str) {
+        let command = if line.trim().is_empty() {
+            self.last_command
+        } else {
+            match DebugCommand::parse(line) {
+                Ok(command) => Some(command),
+                Err(error) => {
+                    println!("{}", error);
+                    None
+                }
+            }
+        };
+        if let Some(command) = command {
+            self.last_command = Some(command);
+            println!("{}", self.machine.debug(command));
         }
+        prompt();
+    }
 
-        println!("{:#} bytes loaded to memory..", program.len());
+    /// Starts recording a GIF of the game in the current palette, or
+    /// finishes the one being recorded.
+    pub fn toggle_video(&mut self) {
+        if self.machine.video.is_some() {
+            match self.machine.stop_video() {
+                Ok(Some(path)) => println!("Saved video to {}", path),
+                Ok(None) => {}
+                Err(error) => println!("{}", error),
+            }
+        } else {
+            let path = self.machine.video_path();
+            match self.machine.start_video(&path, &self.display.palette) {
+                Ok(()) => println!("Recording video to {}", path),
+                Err(error) => println!("{}: {}", path, error),
+            }
+        }
     }
 
-    pub fn render(&mut self, args: &RenderArgs) {
-        let mut memory = self.memory.lock().unwrap();
-
-        // if memory.vram_changed {
-            memory.vram_changed = false;
-            self.gfx.draw(args.viewport(), |_ctx, gfx| {
-                graphics::clear(BACKGROUND, gfx);
-            });
-
-            let square = graphics::rectangle::square(0.0, 0.0, SCALE as f64);
-
-            self.gfx.draw(args.viewport(), |c, gfx| {
-                for y in 0..CHIP8_HEIGHT {
-                    for x in 0..CHIP8_WIDTH {
-                        let pos_x = x * SCALE;
-                        let pos_y = y * SCALE;
-                        if memory.vram[y][x] == 1 {
-                            graphics::rectangle(FOREGROUND, square, c.transform.trans(pos_x as f64, pos_y as f64), gfx);
-                        }
-                    }
+    /// Steps one frame back in time, if there is any history left.
+    pub fn rewind_frame(&mut self) {
+        if let Some(state) = self.rewind.pop() {
+            if let Err(error) = self.machine.load_state(&state) {
+                println!("{}", error);
+            }
+        }
+    }
+
+    /// How many machine frames run per 60 Hz update.
+    pub fn speed(&self) -> f64 {
+        if self.turbo {
+            TURBO_SPEED
+        } else if self.slow_motion {
+            SLOW_MOTION_SPEED
+        } else {
+            1.0
+        }
+    }
+
+    /// Saves the machine to a numbered slot, or loads it back.
+    pub fn state_slot(&mut self, slot: u8, save: bool) {
+        let path = self.machine.state_slot_path(slot);
+        let result = if save {
+            self.machine.save_state_file(&path)
+        } else {
+            self.machine.load_state_file(&path)
+        };
+
+        match result {
+            Ok(()) if save => println!("Saved state to {}", path),
+            Ok(()) => println!("Loaded state from {}", path),
+            Err(error) => println!("{}: {}", path, error),
+        }
+    }
+
+    /// Routes a key through the configured bindings, to the keypad, a hotkey
+    /// or the debugger.
+    pub fn bound_key(&mut self, key: Key, state: ButtonState) {
+        let name = format!("{:?}", key);
+        if let Some(hex) = self.bindings.keypad_key(&name) {
+            match state {
+                ButtonState::Press => self.machine.press_key(hex),
+                ButtonState::Release => self.machine.release_key(hex),
+            }
+        } else if let Some(hotkey) = self.bindings.hotkey(&name) {
+            self.hotkey(hotkey, state);
+        } else if let Some(command) = self.bindings.debug_command(&name) {
+            if state == ButtonState::Press {
+                println!("{}", self.machine.debug(command));
+                prompt();
+            }
+        }
+    }
+
+    /// Resizes the window to `scale` times the lores screen.
+    pub fn set_scale(&mut self, scale: usize) {
+        self.window.set_size([(CHIP8_WIDTH * scale) as u32, (CHIP8_HEIGHT * scale) as u32]);
+    }
+
+    /// Switches between fullscreen on the window's monitor and a window.
+    pub fn set_fullscreen(&mut self, fullscreen: bool) {
+        let window = self.window.window.window();
+        window.set_fullscreen(if fullscreen { Some(window.get_current_monitor()) } else { None });
+        self.fullscreen = fullscreen;
+    }
+
+    pub fn update_title(&mut self) {
+        let mut title = format!("Chip8 - {} ipf", self.machine.instructions_per_frame);
+        if self.speed() != 1.0 {
+            title.push_str(&format!(" (x{})", self.speed()));
+        }
+        if self.rewinding {
+            title.push_str(" (rewinding)");
+        }
+        self.window.set_title(title);
+    }
+
+    /// Carries out a hotkey. Turbo and rewind last while the key is held;
+    /// the others act when it is pressed. Movies cannot replay speed changes
+    /// or rewinding, so those are refused while one is recorded or played.
+    pub fn hotkey(&mut self, hotkey: Hotkey, state: ButtonState) {
+        let pressed = state == ButtonState::Press;
+        match hotkey {
+            Hotkey::Turbo => self.turbo = pressed,
+            Hotkey::Faster | Hotkey::Slower | Hotkey::Rewind if self.machine.movie_active() => {
+                if pressed {
+                    println!("{}", Chip8Error::MovieActive);
                 }
-            })
-        // }
+                return;
+            }
+            Hotkey::Rewind => self.rewinding = pressed,
+            _ if !pressed => return,
+            Hotkey::SlowMotion => self.slow_motion = !self.slow_motion,
+            Hotkey::Faster => {
+                let ipf = self.machine.instructions_per_frame;
+                let faster = (ipf.saturating_mul(5) / 4).max(ipf.saturating_add(1));
+                self.machine.instructions_per_frame = faster.min(MAX_INSTRUCTIONS_PER_FRAME);
+            }
+            Hotkey::Slower => {
+                let ipf = self.machine.instructions_per_frame;
+                self.machine.instructions_per_frame = (ipf * 4 / 5).max(1);
+            }
+            Hotkey::SaveState(slot) => self.state_slot(slot, true),
+            Hotkey::LoadState(slot) => self.state_slot(slot, false),
+            Hotkey::Palette => {
+                self.display.palette = self.display.palette.next();
+                println!("Palette: {}", self.display.palette.name);
+            }
+            Hotkey::Fullscreen => {
+                let fullscreen = !self.fullscreen;
+                self.set_fullscreen(fullscreen);
+            }
+            Hotkey::Record => self.toggle_video(),
+            Hotkey::Screenshot => match self.machine.save_screenshot(&self.display.palette) {
+                Ok(paths) => println!("Saved screenshot to {}", paths.join(" and ")),
+                Err(error) => println!("{}", error),
+            },
+        }
+        self.update_title();
+    }
+
+    pub fn load_program(&mut self, program: &[u8]) -> Result<(), Chip8Error> {
+        self.machine.load_program(program)
+    }
+
+    /// Presents the machine's framebuffer, swapping buffers only when the
+    /// display drew anything.
+    pub fn render(&mut self, args: &RenderArgs) {
+        self.machine.present(&mut self.display);
+        if self.display.render(args) {
+            self.window.swap_buffers();
+        }
     }
 
+    /// Runs the window until it is closed, reading debugger commands from
+    /// stdin meanwhile.
     pub fn boot(&mut self) {
         println!("Booting Chip8..");
+        let console = spawn_console();
+        prompt();
 
-        let mut events = Events::new(EventSettings::new()).ups(180);
+        let mut events = Events::new(EventSettings::new()).ups(TIMER_FREQ).swap_buffers(false);
+        self.update_title();
 
         while let Some(e) = events.next(&mut self.window) {
+            if e.focus_args().is_some() {
+                self.display.invalidate();
+            }
             if let Some(args) = e.render_args() {
                 self.render(&args);
             }
 
             if let Some(_u) = e.update_args() {
-                if self.debugger.mode != DebugMode::Step {
-                    let mut cpu = self.cpu.lock().unwrap();
-                    cpu.tick(&self.keyboard, &self.debugger);
+                while let Ok(line) = console.try_recv() {
+                    self.console_command(&line);
+                }
+                if !self.machine.debugger.paused || self.rewinding {
+                    self.frame_budget += self.speed();
+                }
+                while self.frame_budget >= 1.0 {
+                    self.frame_budget -= 1.0;
+                    if self.rewinding {
+                        self.rewind_frame();
+                    } else if !self.machine.debugger.paused {
+                        self.run_frame();
+                    }
+                }
+                if let Some(reason) = self.machine.debugger.stopped.take() {
+                    println!("\n{}", reason);
+                    prompt();
+                }
+                if let Some(error) = self.machine.video_error.take() {
+                    println!("\nStopped recording video: {}", error);
+                    prompt();
                 }
             }
 
             if let Some(k) = e.button_args() {
-                if k.state == ButtonState::Press || k.state == ButtonState::Release {
-                    if k.state == ButtonState::Press {
-                        self.debugger.input_key(&k.button, &self.keyboard);
-                    }
-                    let index = match &k.button {
-                        &Button::Keyboard(Key::D1)  => Some(0x1),
-                        &Button::Keyboard(Key::D2)  => Some(0x2),
-                        &Button::Keyboard(Key::D3)  => Some(0x3),
-                        &Button::Keyboard(Key::D4)  => Some(0xc),
-                        &Button::Keyboard(Key::Q)   => Some(0x4),
-                        &Button::Keyboard(Key::W)   => Some(0x5),
-                        &Button::Keyboard(Key::E)   => Some(0x6),
-                        &Button::Keyboard(Key::R)   => Some(0xd),
-                        &Button::Keyboard(Key::A)   => Some(0x7),
-                        &Button::Keyboard(Key::S)   => Some(0x8),
-                        &Button::Keyboard(Key::D)   => Some(0x9),
-                        &Button::Keyboard(Key::F)   => Some(0xe),
-                        &Button::Keyboard(Key::Z)   => Some(0xa),
-                        &Button::Keyboard(Key::X)   => Some(0x0),
-                        &Button::Keyboard(Key::C)   => Some(0xb),
-                        &Button::Keyboard(Key::V)   => Some(0xf),
-                        _ => None,
-                    };
-
-                    if let Some(i) = index {
-                        match k.state {
-                            ButtonState::Press => self.keyboard.press(i),
-                            ButtonState::Release => self.keyboard.release(i),
-                        }
-                    }
+                if let Button::Keyboard(key) = k.button {
+                    self.bound_key(key, k.state);
                 }
             }
         }
     }
-}
\ No newline at end of file
+}
+
+fn prompt() {
+    print!("{}", DEBUG_PROMPT);
+    let _ = io::stdout().flush();
+}
@@ -0,0 +1,480 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use toml::Value;
+use toml::value::Table;
+use debugger::{DebugCommand, DEFAULT_DISASSEMBLE_LINES};
+use error::Chip8Error;
+use palette::Palette;
+use phosphor::PhosphorMode;
+
+/// Looked up in the working directory when `--config` is not given.
+pub const DEFAULT_CONFIG_PATH: &str = "chip8.toml";
+
+/// The usual layout, mapping the left block of a QWERTY keyboard onto the
+/// 4x4 hex keypad:
+///
+/// ```text
+/// 1 2 3 C      1 2 3 4
+/// 4 5 6 D      Q W E R
+/// 7 8 9 E  =>  A S D F
+/// A 0 B F      Z X C V
+/// ```
+pub const DEFAULT_KEYPAD: [(u8, &str); 16] = [
+    (0x1, "D1"), (0x2, "D2"), (0x3, "D3"), (0xC, "D4"),
+    (0x4, "Q"), (0x5, "W"), (0x6, "E"), (0xD, "R"),
+    (0x7, "A"), (0x8, "S"), (0x9, "D"), (0xE, "F"),
+    (0xA, "Z"), (0x0, "X"), (0xB, "C"), (0xF, "V"),
+];
+
+pub const DEFAULT_DEBUGGER: [(DebugCommand, &str); 8] = [
+    (DebugCommand::Continue, "D0"),
+    (DebugCommand::Break(None), "D9"),
+    (DebugCommand::Trace(None), "D8"),
+    (DebugCommand::Step(1), "N"),
+    (DebugCommand::Next, "M"),
+    (DebugCommand::Finish, "B"),
+    (DebugCommand::Registers, "L"),
+    (DebugCommand::Disassemble(None, DEFAULT_DISASSEMBLE_LINES), "O"),
+];
+
+pub const DEFAULT_HOTKEYS: [(Hotkey, &str); 17] = [
+    (Hotkey::Turbo, "Tab"),
+    (Hotkey::SlowMotion, "Backquote"),
+    (Hotkey::Faster, "Equals"),
+    (Hotkey::Slower, "Minus"),
+    (Hotkey::Rewind, "Backspace"),
+    (Hotkey::SaveState(1), "F1"),
+    (Hotkey::SaveState(2), "F2"),
+    (Hotkey::SaveState(3), "F3"),
+    (Hotkey::SaveState(4), "F4"),
+    (Hotkey::LoadState(1), "F5"),
+    (Hotkey::LoadState(2), "F6"),
+    (Hotkey::LoadState(3), "F7"),
+    (Hotkey::LoadState(4), "F8"),
+    (Hotkey::Palette, "F9"),
+    (Hotkey::Fullscreen, "F10"),
+    (Hotkey::Record, "F11"),
+    (Hotkey::Screenshot, "F12"),
+];
+
+/// Key names besides the letters, `D0`-`D9`, `F1`-`F24` and `NumPad0`-`NumPad9`.
+const NAMED_KEYS: [&str; 165] = [
+    "Backspace", "Tab", "Return", "Escape", "Space", "Exclaim", "Quotedbl", "Hash", "Dollar",
+    "Percent", "Ampersand", "Quote", "LeftParen", "RightParen", "Asterisk", "Plus", "Comma",
+    "Minus", "Period", "Slash", "Colon", "Semicolon", "Less", "Equals", "Greater", "Question",
+    "At", "LeftBracket", "Backslash", "RightBracket", "Caret", "Underscore", "Backquote", "Delete",
+    "CapsLock", "PrintScreen", "ScrollLock", "Pause", "Insert", "Home", "PageUp", "End",
+    "PageDown", "Right", "Left", "Down", "Up", "NumLockClear", "NumPadDivide", "NumPadMultiply",
+    "NumPadMinus", "NumPadPlus", "NumPadEnter", "NumPadPeriod", "Application", "Power",
+    "NumPadEquals", "Execute", "Help", "Menu", "Select", "Stop", "Again", "Undo", "Cut", "Copy",
+    "Paste", "Find", "Mute", "VolumeUp", "VolumeDown", "NumPadComma", "NumPadEqualsAS400",
+    "AltErase", "Sysreq", "Cancel", "Clear", "Prior", "Return2", "Separator", "Out", "Oper",
+    "ClearAgain", "CrSel", "ExSel", "NumPad00", "NumPad000", "ThousandsSeparator",
+    "DecimalSeparator", "CurrencyUnit", "CurrencySubUnit", "NumPadLeftParen", "NumPadRightParen",
+    "NumPadLeftBrace", "NumPadRightBrace", "NumPadTab", "NumPadBackspace", "NumPadA", "NumPadB",
+    "NumPadC", "NumPadD", "NumPadE", "NumPadF", "NumPadXor", "NumPadPower", "NumPadPercent",
+    "NumPadLess", "NumPadGreater", "NumPadAmpersand", "NumPadDblAmpersand", "NumPadVerticalBar",
+    "NumPadDblVerticalBar", "NumPadColon", "NumPadHash", "NumPadSpace", "NumPadAt", "NumPadExclam",
+    "NumPadMemStore", "NumPadMemRecall", "NumPadMemClear", "NumPadMemAdd", "NumPadMemSubtract",
+    "NumPadMemMultiply", "NumPadMemDivide", "NumPadPlusMinus", "NumPadClear", "NumPadClearEntry",
+    "NumPadBinary", "NumPadOctal", "NumPadDecimal", "NumPadHexadecimal", "LCtrl", "LShift", "LAlt",
+    "LGui", "RCtrl", "RShift", "RAlt", "RGui", "Mode", "AudioNext", "AudioPrev", "AudioStop",
+    "AudioPlay", "AudioMute", "MediaSelect", "Www", "Mail", "Calculator", "Computer", "AcSearch",
+    "AcHome", "AcBack", "AcForward", "AcStop", "AcRefresh", "AcBookmarks", "BrightnessDown",
+    "BrightnessUp", "DisplaySwitch", "KbdIllumToggle", "KbdIllumDown", "KbdIllumUp", "Eject",
+    "Sleep",
+];
+
+/// Frontend actions bound to keys in the `[hotkeys]` table.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Hotkey {
+    /// Fast-forwards while held.
+    Turbo,
+    SlowMotion,
+    /// Raises the instructions per frame.
+    Faster,
+    Slower,
+    /// Rewinds while held.
+    Rewind,
+    SaveState(u8),
+    LoadState(u8),
+    Palette,
+    /// Switches the window between fullscreen and windowed.
+    Fullscreen,
+    Record,
+    Screenshot,
+}
+
+impl Hotkey {
+    /// Looks up a hotkey by its name in the config: `turbo`, `slow_motion`,
+    /// `faster`, `slower`, `rewind`, `save_state_1`-`save_state_9`,
+    /// `load_state_1`-`load_state_9`, `palette`, `fullscreen`, `record` or
+    /// `screenshot`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        let slot = |prefix: &str| {
+            name.strip_prefix(prefix).and_then(|slot| slot.parse().ok()).filter(|slot| (1..=9).contains(slot))
+        };
+        let hotkey = match name {
+            "turbo" => Hotkey::Turbo,
+            "slow_motion" => Hotkey::SlowMotion,
+            "faster" => Hotkey::Faster,
+            "slower" => Hotkey::Slower,
+            "rewind" => Hotkey::Rewind,
+            "palette" => Hotkey::Palette,
+            "fullscreen" => Hotkey::Fullscreen,
+            "record" => Hotkey::Record,
+            "screenshot" => Hotkey::Screenshot,
+            _ => match (slot("save_state_"), slot("load_state_")) {
+                (Some(slot), _) => Hotkey::SaveState(slot),
+                (_, Some(slot)) => Hotkey::LoadState(slot),
+                _ => return None,
+            },
+        };
+        Some(hotkey)
+    }
+}
+
+/// Host key names bound to keypad keys, hotkeys and debugger commands. Names
+/// follow the frontend's key names (`W`, `Up`, `NumPad8`, ...) and are
+/// matched case insensitively; a bare digit such as `1` stands for `D1`.
+///
+/// Keypad bindings take precedence, so a key given to the game never reaches
+/// the hotkeys or the debugger. A key bound to a hotkey is taken away from
+/// the debugger and the other way round.
+#[derive(Clone, Debug, PartialEq)]
+pub struct KeyBindings {
+    pub keypad: Vec<(String, u8)>,
+    pub debugger: Vec<(String, DebugCommand)>,
+    pub hotkeys: Vec<(String, Hotkey)>,
+}
+
+impl KeyBindings {
+    pub fn keypad_key(&self, name: &str) -> Option<u8> {
+        self.keypad.iter().find(|&(bound, _)| bound.eq_ignore_ascii_case(name)).map(|&(_, key)| key)
+    }
+
+    pub fn debug_command(&self, name: &str) -> Option<DebugCommand> {
+        if self.keypad_key(name).is_some() {
+            return None;
+        }
+        self.debugger.iter().find(|&(bound, _)| bound.eq_ignore_ascii_case(name)).map(|&(_, command)| command)
+    }
+
+    pub fn hotkey(&self, name: &str) -> Option<Hotkey> {
+        if self.keypad_key(name).is_some() {
+            return None;
+        }
+        self.hotkeys.iter().find(|&(bound, _)| bound.eq_ignore_ascii_case(name)).map(|&(_, hotkey)| hotkey)
+    }
+
+    /// The first key bound to `hotkey`, for help texts.
+    pub fn hotkey_name(&self, hotkey: Hotkey) -> Option<&str> {
+        self.hotkeys.iter()
+            .find(|&&(ref name, bound)| bound == hotkey && self.keypad_key(name).is_none())
+            .map(|(name, _)| name.as_str())
+    }
+
+    /// Rebinds every keypad key listed in `table`, leaving the others alone.
+    /// Host keys named here are taken away from whatever they were bound to.
+    fn apply_keypad(&mut self, table: &Table) -> Result<(), Chip8Error> {
+        for (hex, names) in table.iter() {
+            let key = u8::from_str_radix(hex, 16).ok().filter(|&key| key < 16)
+                .ok_or_else(|| invalid(&format!("'{}' is not a keypad key, expected 0-F", hex)))?;
+            let names = key_names(names).ok_or_else(|| invalid(&format!("keypad key {} expects a key name or a list of them", hex)))?;
+            check_key_names(&names)?;
+            self.keypad.retain(|(name, bound)| *bound != key && !contains(&names, name));
+            self.keypad.extend(names.into_iter().map(|name| (name, key)));
+        }
+        Ok(())
+    }
+
+    /// Rebinds every debugger command listed in `table`. Commands are
+    /// written as at the console, so `"x/16 0x200" = "M"` works too.
+    fn apply_debugger(&mut self, table: &Table) -> Result<(), Chip8Error> {
+        for (name, keys) in table.iter() {
+            let command = DebugCommand::parse(name)
+                .map_err(|error| invalid(&format!("debugger binding '{}': {}", name, error)))?;
+            let keys = key_names(keys).ok_or_else(|| invalid(&format!("debugger command {} expects a key name or a list of them", name)))?;
+            check_key_names(&keys)?;
+            self.debugger.retain(|(key, bound)| *bound != command && !contains(&keys, key));
+            self.hotkeys.retain(|(key, _)| !contains(&keys, key));
+            self.debugger.extend(keys.into_iter().map(|key| (key, command)));
+        }
+        Ok(())
+    }
+
+    /// Rebinds every hotkey listed in `table`.
+    fn apply_hotkeys(&mut self, table: &Table) -> Result<(), Chip8Error> {
+        for (name, keys) in table.iter() {
+            let hotkey = Hotkey::from_name(name).ok_or_else(|| invalid(&format!("unknown hotkey '{}'", name)))?;
+            let keys = key_names(keys).ok_or_else(|| invalid(&format!("hotkey {} expects a key name or a list of them", name)))?;
+            check_key_names(&keys)?;
+            self.hotkeys.retain(|(key, bound)| *bound != hotkey && !contains(&keys, key));
+            self.debugger.retain(|(key, _)| !contains(&keys, key));
+            self.hotkeys.extend(keys.into_iter().map(|key| (key, hotkey)));
+        }
+        Ok(())
+    }
+
+    fn apply(&mut self, table: &Table) -> Result<(), Chip8Error> {
+        if let Some(keypad) = section(table, "keypad")? {
+            self.apply_keypad(keypad)?;
+        }
+        if let Some(debugger) = section(table, "debugger")? {
+            self.apply_debugger(debugger)?;
+        }
+        if let Some(hotkeys) = section(table, "hotkeys")? {
+            self.apply_hotkeys(hotkeys)?;
+        }
+        Ok(())
+    }
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        KeyBindings {
+            keypad: DEFAULT_KEYPAD.iter().map(|&(key, name)| (name.to_string(), key)).collect(),
+            debugger: DEFAULT_DEBUGGER.iter().map(|&(command, name)| (name.to_string(), command)).collect(),
+            hotkeys: DEFAULT_HOTKEYS.iter().map(|&(hotkey, name)| (name.to_string(), hotkey)).collect(),
+        }
+    }
+}
+
+/// Frontend settings read from a TOML file:
+///
+/// ```toml
+/// palette = "amber"
+/// phosphor = "decay:0.6"
+///
+/// [keypad]
+/// 5 = ["W", "Up"]
+///
+/// [debugger]
+/// step = "F10"
+///
+/// [hotkeys]
+/// turbo = "LShift"
+///
+/// [roms.pong]
+/// palette = "vip"
+///
+/// [roms.pong.keypad]
+/// 1 = "D1"
+/// 4 = "Q"
+/// C = "Up"
+/// D = "Down"
+/// ```
+///
+/// Tables under `roms` are keyed by the ROM's file name without extension
+/// and apply on top of the global ones.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Config {
+    pub bindings: KeyBindings,
+    pub palette: Option<Palette>,
+    pub phosphor: Option<PhosphorMode>,
+}
+
+impl Config {
+    pub fn parse(text: &str, rom_name: &str) -> Result<Self, Chip8Error> {
+        let root = match text.parse::<Value>() {
+            Ok(Value::Table(root)) => root,
+            Ok(_) => return Err(invalid("expected a table")),
+            Err(error) => return Err(invalid(&error.to_string())),
+        };
+
+        let mut config = Config::default();
+        config.apply(&root)?;
+        if let Some(roms) = section(&root, "roms")? {
+            if let Some(rom) = section(roms, rom_name)? {
+                config.apply(rom)?;
+            }
+        }
+        Ok(config)
+    }
+
+    fn apply(&mut self, table: &Table) -> Result<(), Chip8Error> {
+        self.bindings.apply(table)?;
+        match table.get("palette") {
+            Some(Value::String(name)) => {
+                self.palette = Some(Palette::from_name(name).ok_or_else(|| {
+                    invalid(&format!("unknown palette '{}', expected one of: {}", name, Palette::names().join(", ")))
+                })?);
+            }
+            Some(_) => return Err(invalid("palette expects a name")),
+            None => {}
+        }
+        match table.get("phosphor") {
+            Some(Value::String(name)) => {
+                self.phosphor = Some(PhosphorMode::from_name(name).ok_or_else(|| {
+                    invalid(&format!("invalid phosphor mode '{}', expected off, decay[:0-1] or or[:FRAMES]", name))
+                })?);
+            }
+            Some(_) => return Err(invalid("phosphor expects a mode")),
+            None => {}
+        }
+        Ok(())
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P, rom_name: &str) -> Result<Self, Chip8Error> {
+        let mut text = String::new();
+        File::open(path)?.read_to_string(&mut text)?;
+        Self::parse(&text, rom_name)
+    }
+}
+
+fn section<'a>(table: &'a Table, name: &str) -> Result<Option<&'a Table>, Chip8Error> {
+    match table.get(name) {
+        Some(Value::Table(section)) => Ok(Some(section)),
+        Some(_) => Err(invalid(&format!("'{}' must be a table", name))),
+        None => Ok(None),
+    }
+}
+
+/// Accepts either a single key name or an array of them.
+fn key_names(value: &Value) -> Option<Vec<String>> {
+    match *value {
+        Value::String(ref name) => Some(vec![key_name(name)]),
+        Value::Array(ref names) => names.iter().map(|name| name.as_str().map(key_name)).collect(),
+        _ => None,
+    }
+}
+
+fn check_key_names(names: &[String]) -> Result<(), Chip8Error> {
+    match names.iter().find(|name| !is_key_name(name)) {
+        Some(name) => Err(invalid(&format!("unknown key name '{}'", name))),
+        None => Ok(()),
+    }
+}
+
+fn is_key_name(name: &str) -> bool {
+    let number = |prefix: &str, range: ::std::ops::RangeInclusive<u8>| {
+        name.get(..prefix.len()).is_some_and(|start| start.eq_ignore_ascii_case(prefix))
+            && name[prefix.len()..].parse().is_ok_and(|n: u8| range.contains(&n) && n.to_string() == name[prefix.len()..])
+    };
+    (name.len() == 1 && name.as_bytes()[0].is_ascii_alphabetic())
+        || number("D", 0..=9)
+        || number("F", 1..=24)
+        || number("NumPad", 0..=9)
+        || NAMED_KEYS.iter().any(|key| key.eq_ignore_ascii_case(name))
+}
+
+fn contains(names: &[String], name: &str) -> bool {
+    names.iter().any(|bound| bound.eq_ignore_ascii_case(name))
+}
+
+fn key_name(name: &str) -> String {
+    match name.as_bytes() {
+        [digit] if digit.is_ascii_digit() => format!("D{}", name),
+        _ => name.to_string(),
+    }
+}
+
+fn invalid(reason: &str) -> Chip8Error {
+    Chip8Error::InvalidConfig(reason.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use machine::MEMORY_SIZE;
+
+    fn parse(text: &str) -> Result<Config, Chip8Error> {
+        Config::parse(text, "pong")
+    }
+
+    #[test]
+    fn defaults_without_a_file() {
+        let config = parse("").unwrap();
+        assert_eq!(config, Config::default());
+        assert_eq!(config.bindings.keypad_key("W"), Some(0x5));
+        assert_eq!(config.bindings.keypad_key("d1"), Some(0x1));
+        assert_eq!(config.bindings.debug_command("N"), Some(DebugCommand::Step(1)));
+        assert_eq!(config.bindings.hotkey("F5"), Some(Hotkey::LoadState(1)));
+    }
+
+    #[test]
+    fn overrides_keypad_keys() {
+        let bindings = parse("[keypad]\n5 = \"Up\"\nA = \"1\"").unwrap().bindings;
+        assert_eq!(bindings.keypad_key("Up"), Some(0x5));
+        assert_eq!(bindings.keypad_key("W"), None);
+        assert_eq!(bindings.keypad_key("D1"), Some(0xA));
+        assert_eq!(bindings.keypad_key("Z"), None);
+        assert_eq!(bindings.keypad_key("Q"), Some(0x4));
+    }
+
+    #[test]
+    fn accepts_lists_of_keys() {
+        let bindings = parse("[keypad]\n5 = [\"W\", \"Up\", \"NumPad8\"]\n[debugger]\nstep = [\"F10\", \"K\"]").unwrap().bindings;
+        for key in ["W", "Up", "NumPad8"].iter() {
+            assert_eq!(bindings.keypad_key(key), Some(0x5));
+        }
+        assert_eq!(bindings.debug_command("F10"), Some(DebugCommand::Step(1)));
+        assert_eq!(bindings.debug_command("K"), Some(DebugCommand::Step(1)));
+        assert_eq!(bindings.debug_command("N"), None);
+    }
+
+    #[test]
+    fn layers_rom_tables_over_the_global_ones() {
+        let text = "palette = \"amber\"\n[keypad]\nC = \"Up\"\n\
+                    [roms.pong]\npalette = \"vip\"\n[roms.pong.keypad]\nD = \"Down\"\n\
+                    [roms.tetris.keypad]\nC = \"Left\"";
+        let config = parse(text).unwrap();
+        assert_eq!(config.palette, Palette::from_name("vip"));
+        assert_eq!(config.bindings.keypad_key("Up"), Some(0xC));
+        assert_eq!(config.bindings.keypad_key("Down"), Some(0xD));
+        assert_eq!(config.bindings.keypad_key("Left"), None);
+
+        let other = Config::parse(text, "breakout").unwrap();
+        assert_eq!(other.palette, Palette::from_name("amber"));
+        assert_eq!(other.bindings.keypad_key("Down"), None);
+    }
+
+    #[test]
+    fn parses_debugger_commands() {
+        let text = "[debugger]\n\"x/32 0x200\" = \"K\"\nstep_mode = \"P\"\ndump_memory = \"U\"";
+        let bindings = parse(text).unwrap().bindings;
+        assert_eq!(bindings.debug_command("K"), Some(DebugCommand::Examine(32, 0x200)));
+        assert_eq!(bindings.debug_command("P"), Some(DebugCommand::Break(None)));
+        assert_eq!(bindings.debug_command("U"), Some(DebugCommand::Examine(MEMORY_SIZE, 0)));
+    }
+
+    #[test]
+    fn rebinds_hotkeys() {
+        let bindings = parse("[hotkeys]\nturbo = \"LShift\"\nsave_state_5 = \"N\"\n[keypad]\n1 = \"F1\"").unwrap().bindings;
+        assert_eq!(bindings.hotkey("LShift"), Some(Hotkey::Turbo));
+        assert_eq!(bindings.hotkey("Tab"), None);
+        assert_eq!(bindings.hotkey("N"), Some(Hotkey::SaveState(5)));
+        assert_eq!(bindings.debug_command("N"), None);
+        assert_eq!(bindings.hotkey("F1"), None);
+        assert_eq!(bindings.hotkey_name(Hotkey::SaveState(1)), None);
+
+        let bindings = parse("[debugger]\ncontinue = \"F12\"").unwrap().bindings;
+        assert_eq!(bindings.hotkey("F12"), None);
+        assert_eq!(bindings.debug_command("F12"), Some(DebugCommand::Continue));
+    }
+
+    #[test]
+    fn rejects_bad_entries() {
+        let texts = [
+            "palette = ",
+            "keypad = \"W\"",
+            "[roms]\npong = 5",
+            "[debugger]\nfly = \"K\"",
+            "[debugger]\nstep = 5",
+            "[hotkeys]\nturbo = \"Tab\"\nwarp = \"K\"",
+            "[hotkeys]\nsave_state_0 = \"K\"",
+            "[keypad]\nG = \"W\"",
+            "[keypad]\n5 = [\"W\", 5]",
+            "[keypad]\n5 = \"Wup\"",
+            "[keypad]\n5 = \"D10\"",
+            "[debugger]\nstep = \"F25\"",
+            "[hotkeys]\nturbo = \"Shift\"",
+            "palette = \"sepia\"",
+            "phosphor = 3",
+        ];
+        for text in texts.iter() {
+            assert!(parse(text).is_err(), "{}", text);
+        }
+    }
+}
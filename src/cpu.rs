@@ -1,9 +1,13 @@
-use chip8::{OPCODE_SIZE, CHIP8_WIDTH, CHIP8_HEIGHT};
+use machine::{OPCODE_SIZE, PROGRAM_START, CHIP8_WIDTH, CHIP8_HEIGHT, SCHIP_WIDTH, SCHIP_HEIGHT, VIP_HIRES_WIDTH, VIP_HIRES_HEIGHT, BIG_FONT_ADDR};
 use memory::Memory;
-use hardware::KeyboardDriver;
+use hardware::{KeyboardDriver, RandomDriver};
+use drivers::XorShiftRandom;
 use std::sync::{Arc, Mutex};
-use rand::{self, Rng};
-use debugger::{Debugger, DebugMode};
+use rand;
+use debugger::Debugger;
+use quirks::Quirks;
+use error::Chip8Error;
+use instruction::decode;
 
 pub enum Action {
     Next,
@@ -12,6 +16,8 @@ pub enum Action {
     Jump(u16),
 }
 
+pub type OpResult = Result<Action, Chip8Error>;
+
 pub struct Cpu {
     pub debug: bool,
     pub v: [u8; 16],
@@ -24,11 +30,23 @@ pub struct Cpu {
     pub halt: bool,
     pub keypad_waiting: bool,
     pub keypad_register: u8,
+    /// The key pressed during the current `Fx0A` wait, completed on release.
+    pub keypad_key: Option<u8>,
+    pub rpl: [u8; 16],
+    pub plane: u8,
+    /// The XO-CHIP sample loaded by `F002`, if any; until then the beeper
+    /// plays a plain tone.
+    pub audio_pattern: Option<[u8; 16]>,
+    pub pitch: u8,
     pub steps: usize,
+    pub quirks: Quirks,
+    pub seed: u64,
+    pub rng: Box<dyn RandomDriver + Send>,
 }
 
 impl Cpu {
-    pub fn new(memory: Arc<Mutex<Memory>>) -> Self {
+    pub fn new(memory: Arc<Mutex<Memory>>, quirks: Quirks) -> Self {
+        let seed = rand::random();
         Cpu {
             v: [0; 16],
             i: 0,
@@ -40,341 +58,779 @@ impl Cpu {
             halt: false,
             keypad_waiting: false,
             keypad_register: 0,
+            keypad_key: None,
+            rpl: [0; 16],
+            plane: 1,
+            audio_pattern: None,
+            pitch: 64,
             debug: false,
             steps: 0,
+            quirks,
+            seed,
+            rng: Box::new(XorShiftRandom::new(seed)),
         }
     }
 
+    /// Restarts the random number sequence used by `Cxkk` from `seed`.
+    pub fn reseed(&mut self, seed: u64) {
+        self.seed = seed;
+        self.rng.reseed(seed);
+    }
+
+    /// Replaces the random source, seeding it with the current seed.
+    pub fn set_random_driver(&mut self, mut rng: Box<dyn RandomDriver + Send>) {
+        rng.reseed(self.seed);
+        self.rng = rng;
+    }
+
     pub fn opcode(&self) -> u16 {
+        self.word_at(self.pc)
+    }
+
+    pub fn word_at(&self, addr: u16) -> u16 {
         let memory = self.memory.lock().unwrap();
-        (memory.ram[self.pc as usize] as u16) << 8 | (memory.ram[(self.pc + 1) as usize] as u16)
+        (memory.ram[addr as usize] as u16) << 8 | (memory.ram[addr.wrapping_add(1) as usize] as u16)
     }
 
-    pub fn tick<K>(&mut self, keyboard: &K, debugger: &Debugger) where K: KeyboardDriver {
+    pub fn tick<K>(&mut self, keyboard: &mut K, debugger: &Debugger) -> Result<(), Chip8Error>
+        where K: KeyboardDriver
+    {
         if !self.halt {
-            if self.delay_timer > 0 {
-                self.delay_timer -= 1
-            }
-
             if !self.keypad_waiting {
-                if self.sound_timer > 0 {
-                    self.sound_timer -= 1
-                }
                 let opcode = self.opcode();
-                self.run_opcode(opcode, keyboard, debugger);
-            } else {
-                // println!("Waiting for key");
-                let key = keyboard.get_key();
-                if let Some(k) = key {
-                    self.keypad_waiting = false;
-                    self.v[self.keypad_register as usize] = k;
-                }
+                self.run_opcode(opcode, keyboard, debugger)?;
+            } else if let Some(key) = self.waited_key(keyboard) {
+                self.keypad_waiting = false;
+                self.keypad_key = None;
+                self.v[self.keypad_register as usize] = key;
             }
         }
+        Ok(())
     }
 
-    pub fn run_opcode<K>(&mut self, opcode: u16, keyboard: &K, debugger: &Debugger) where K: KeyboardDriver {
+    /// The key completing an `Fx0A` wait, if any. On the VIP the wait only
+    /// ends once the key is let go, so a key held down across the prompt is
+    /// taken once, when released, rather than on every prompt it is held for.
+    fn waited_key<K>(&mut self, keyboard: &mut K) -> Option<u8>
+        where K: KeyboardDriver
+    {
+        if !self.quirks.key_wait_on_release {
+            return keyboard.get_key();
+        }
+        if self.keypad_key.is_none() {
+            self.keypad_key = keyboard.take_press().or_else(|| keyboard.get_key());
+        }
+        let key = self.keypad_key?;
+        if keyboard.take_release(key) {
+            Some(key)
+        } else {
+            None
+        }
+    }
+
+    /// Counts the delay and sound timers down. Called at `TIMER_FREQ`,
+    /// independently of how many instructions run per frame.
+    pub fn tick_timers(&mut self) {
+        if self.delay_timer > 0 {
+            self.delay_timer -= 1
+        }
+
+        if self.sound_timer > 0 {
+            self.sound_timer -= 1
+        }
+    }
+
+    pub fn run_opcode<K>(&mut self, opcode: u16, keyboard: &K, debugger: &Debugger) -> Result<(), Chip8Error>
+        where K: KeyboardDriver
+    {
+        use instruction::Instruction::*;
+
         self.steps += 1;
-        if debugger.mode != DebugMode::Disabled {
-            debugger.debug(&self, opcode);
+        if debugger.trace {
+            debugger.trace_instruction(self, opcode);
         }
 
-        let nibbles = (
-            ((opcode & 0xF000) >> 12) as u8,
-            ((opcode & 0x0F00) >> 8) as u8,
-            ((opcode & 0x00F0) >> 4) as u8,
-            (opcode & 0x000F) as u8,
-        );
-
-        let nnn = (opcode & 0x0FFF) as u16;
-        let kk = (opcode & 0x00FF) as u8;
-        let x = nibbles.1 as usize;
-        let y = nibbles.2 as usize;
-        let n = nibbles.3 as usize;
-
-        let action = match nibbles {
-            (0x0, 0x0, 0x0, 0x0)    => self.halt(),
-            (0x0, 0x0, 0xE, 0x0)    => self.op_00e0(),              // CLS
-            (0x0, 0x0, 0xE, 0xE)    => self.op_00ee(),              // RET
-            (0x1, _, _, _)          => self.op_1nnn(nnn),           // JP addr
-            (0x2, _, _, _)          => self.op_2nnn(nnn),           // CALL addr
-            (0x3, _, _, _)          => self.op_3xkk(x, kk),         // SE Vx, byte
-            (0x4, _, _, _)          => self.op_4xkk(x, kk),         // SNE Vx, byte
-            (0x5, _, _, 0x00)       => self.op_5xy0(x, y),          // SE Vx, Vy
-            (0x6, _, _, _)          => self.op_6xkk(x, kk),         // LD Vx, byte
-            (0x7, _, _, _)          => self.op_7xkk(x, kk),         // ADD Vx, byte
-            (0x8, _, _, 0x00)       => self.op_8xy0(x, y),          // LD Vx, Vy
-            (0x8, _, _, 0x01)       => self.op_8xy1(x, y),          // OR Vx, Vy
-            (0x8, _, _, 0x02)       => self.op_8xy2(x, y),          // AND Vx, Vy
-            (0x8, _, _, 0x03)       => self.op_8xy3(x, y),          // XOR Vx, Vy
-            (0x8, _, _, 0x04)       => self.op_8xy4(x, y),          // ADD Vx, Vy
-            (0x8, _, _, 0x05)       => self.op_8xy5(x, y),          // SUB Vx, Vy
-            (0x8, _, _, 0x06)       => self.op_8xy6(x, y),          // SHR Vx {, Vy}
-            (0x8, _, _, 0x07)       => self.op_8xy7(x, y),          // SUBN Vx, Vy
-            (0x8, _, _, 0x0E)       => self.op_8xye(x, y),          // SHL Vx {, Vy}
-            (0x9, _, _, 0x00)       => self.op_9xy0(x, y),          // SNE Vx, Vy
-            (0xA, _, _, _)          => self.op_annn(nnn),           // LDI, addr
-            (0xB, _, _, _)          => self.op_bnnn(nnn),           // JP V0, addr
-            (0xC, _, _, _)          => self.op_cxkk(x, kk),         // RND Vx, byte
-            (0xD, _, _, _)          => self.op_dxyn(x, y, n),       // DRW Vx, Vy, nibble
-            (0xE, _, 0x09, 0x0E)    => self.op_ex9e(x, keyboard),   // SKP Vx
-            (0xE, _, 0x0A, 0x01)    => self.op_exa1(x, keyboard),   // SKNP Vx
-            (0xF, _, 0x00, 0x07)    => self.op_fx07(x),         // LD Vx, DT
-            (0xF, _, 0x00, 0x0A)    => self.op_fx0a(x),         // LD Vx, K
-            (0xF, _, 0x01, 0x05)    => self.op_fx15(x),         // LD DT, Vx
-            (0xF, _, 0x01, 0x08)    => self.op_fx18(x),         // LD ST, Vx
-            (0xF, _, 0x01, 0x0E)    => self.op_fx1e(x),         // ADD I, Vx
-            (0xF, _, 0x02, 0x09)    => self.op_fx29(x),         // LD F, Vx
-            (0xF, _, 0x03, 0x03)    => self.op_fx33(x),         // LD B, Vx
-            (0xF, _, 0x05, 0x05)    => self.op_fx55(x),         // LD [I], Vx
-            (0xF, _, 0x06, 0x05)    => self.op_fx65(x),         // LD Vx, [I]
-            _ => unimplemented!("OPCODE {:X}", opcode)
-        };
+        let action = match decode(opcode) {
+            Halt                            => self.halt(),
+            ScrollDown(n)                   => self.op_00cn(n as usize),
+            ScrollUp(n)                     => self.op_00dn(n as usize),
+            Cls                             => self.op_00e0(),
+            Ret                             => self.op_00ee(),
+            ScrollRight                     => self.op_00fb(),
+            ScrollLeft                      => self.op_00fc(),
+            Exit                            => self.op_00fd(),
+            Lores                           => self.op_00fe(),
+            Hires                           => self.op_00ff(),
+            HiresCls                        => self.op_0230(),
+            Jp(0x260) if self.pc == PROGRAM_START as u16
+                                            => self.op_1260(),
+            Jp(nnn)                         => self.op_1nnn(nnn),
+            Call(nnn)                       => self.op_2nnn(nnn),
+            SeByte(x, kk)                   => self.op_3xkk(x as usize, kk),
+            SneByte(x, kk)                  => self.op_4xkk(x as usize, kk),
+            SeReg(x, y)                     => self.op_5xy0(x as usize, y as usize),
+            SaveRange(x, y)                 => self.op_5xy2(x as usize, y as usize),
+            LoadRange(x, y)                 => self.op_5xy3(x as usize, y as usize),
+            LdByte(x, kk)                   => self.op_6xkk(x as usize, kk),
+            AddByte(x, kk)                  => self.op_7xkk(x as usize, kk),
+            LdReg(x, y)                     => self.op_8xy0(x as usize, y as usize),
+            Or(x, y)                        => self.op_8xy1(x as usize, y as usize),
+            And(x, y)                       => self.op_8xy2(x as usize, y as usize),
+            Xor(x, y)                       => self.op_8xy3(x as usize, y as usize),
+            AddReg(x, y)                    => self.op_8xy4(x as usize, y as usize),
+            Sub(x, y)                       => self.op_8xy5(x as usize, y as usize),
+            Shr(x, y)                       => self.op_8xy6(x as usize, y as usize),
+            Subn(x, y)                      => self.op_8xy7(x as usize, y as usize),
+            Shl(x, y)                       => self.op_8xye(x as usize, y as usize),
+            SneReg(x, y)                    => self.op_9xy0(x as usize, y as usize),
+            LdI(nnn)                        => self.op_annn(nnn),
+            JpV0(nnn)                       => self.op_bnnn(nnn),
+            Rnd(x, kk)                      => self.op_cxkk(x as usize, kk),
+            Drw(x, y, n)                    => self.op_dxyn(x as usize, y as usize, n as usize),
+            Skp(x)                          => self.op_ex9e(x as usize, keyboard),
+            Sknp(x)                         => self.op_exa1(x as usize, keyboard),
+            LdILong                         => self.op_f000(),
+            Plane(n)                        => self.op_fn01(n as usize),
+            Audio                           => self.op_f002(),
+            LdVxDt(x)                       => self.op_fx07(x as usize),
+            LdVxK(x)                        => self.op_fx0a(x as usize),
+            LdDtVx(x)                       => self.op_fx15(x as usize),
+            LdStVx(x)                       => self.op_fx18(x as usize),
+            AddIVx(x)                       => self.op_fx1e(x as usize),
+            LdF(x)                          => self.op_fx29(x as usize),
+            LdHf(x)                         => self.op_fx30(x as usize),
+            LdB(x)                          => self.op_fx33(x as usize),
+            Pitch(x)                        => self.op_fx3a(x as usize),
+            Store(x)                        => self.op_fx55(x as usize),
+            Load(x)                         => self.op_fx65(x as usize),
+            StoreRpl(x)                     => self.op_fx75(x as usize),
+            LoadRpl(x)                      => self.op_fx85(x as usize),
+            Unknown(opcode)                 => Err(Chip8Error::UnknownOpcode { opcode, addr: self.pc }),
+        }?;
 
         match action {
-            Action::Next        => self.pc += OPCODE_SIZE,
-            Action::Skip        => self.pc += 2 * OPCODE_SIZE,
+            Action::Next        => self.pc = self.pc.wrapping_add(OPCODE_SIZE),
+            Action::Skip        => {
+                // XO-CHIP: skipping a long load skips both of its words.
+                self.pc = self.pc.wrapping_add(OPCODE_SIZE);
+                let skipped = if self.opcode() == 0xF000 { 2 * OPCODE_SIZE } else { OPCODE_SIZE };
+                self.pc = self.pc.wrapping_add(skipped);
+            }
             Action::Halt        => self.halt = true,
             Action::Jump(addr)  => self.pc = addr,
         }
+        Ok(())
     }
 }
 
 // Implement OPCODES
 impl Cpu {
-    pub fn halt(&mut self) -> Action {
-        println!("Halting...");
-        Action::Halt
+    pub fn halt(&mut self) -> OpResult {
+        Ok(Action::Halt)
     }
 
-    pub fn op_00e0(&mut self) -> Action {
-        let mut mem = self.memory.lock().unwrap();
-        mem.vram = [[0; CHIP8_WIDTH]; CHIP8_HEIGHT];
-        mem.vram_changed = true;
-        Action::Next
+    pub fn op_00cn(&mut self, n: usize) -> OpResult {
+        self.memory.lock().unwrap().scroll(self.plane, 0, n as isize);
+        Ok(Action::Next)
     }
 
-    pub fn op_00ee(&mut self) -> Action {
+    pub fn op_00dn(&mut self, n: usize) -> OpResult {
+        self.memory.lock().unwrap().scroll(self.plane, 0, -(n as isize));
+        Ok(Action::Next)
+    }
+
+    pub fn op_00e0(&mut self) -> OpResult {
+        self.memory.lock().unwrap().clear_vram(self.plane);
+        Ok(Action::Next)
+    }
+
+    pub fn op_00ee(&mut self) -> OpResult {
         let memory = self.memory.lock().unwrap();
+        if self.sp == 0 {
+            return Err(Chip8Error::StackUnderflow { addr: self.pc });
+        }
         self.sp -= 1;
         self.pc = memory.stack[self.sp as usize];
-        Action::Next
+        Ok(Action::Next)
+    }
+
+    pub fn op_00fb(&mut self) -> OpResult {
+        self.memory.lock().unwrap().scroll(self.plane, 4, 0);
+        Ok(Action::Next)
+    }
+
+    pub fn op_00fc(&mut self) -> OpResult {
+        self.memory.lock().unwrap().scroll(self.plane, -4, 0);
+        Ok(Action::Next)
+    }
+
+    pub fn op_00fd(&mut self) -> OpResult {
+        Ok(Action::Halt)
+    }
+
+    pub fn op_00fe(&mut self) -> OpResult {
+        self.memory.lock().unwrap().resize_vram(CHIP8_WIDTH, CHIP8_HEIGHT);
+        Ok(Action::Next)
+    }
+
+    pub fn op_00ff(&mut self) -> OpResult {
+        self.memory.lock().unwrap().resize_vram(SCHIP_WIDTH, SCHIP_HEIGHT);
+        Ok(Action::Next)
+    }
+
+    /// Clears the 64x64 screen of the two-page VIP hires interpreter.
+    pub fn op_0230(&mut self) -> OpResult {
+        self.memory.lock().unwrap().clear_vram(self.plane);
+        Ok(Action::Next)
+    }
+
+    /// Hires programs start with a jump over the patched interpreter stored
+    /// at 0x200. Seeing it switches to the 64x64 mode and enters the program
+    /// proper at 0x2C0.
+    pub fn op_1260(&mut self) -> OpResult {
+        self.memory.lock().unwrap().resize_vram(VIP_HIRES_WIDTH, VIP_HIRES_HEIGHT);
+        Ok(Action::Jump(0x2C0))
     }
 
-    pub fn op_1nnn(&mut self, nnn: u16) -> Action {
-        Action::Jump(nnn)
+    pub fn op_1nnn(&mut self, nnn: u16) -> OpResult {
+        Ok(Action::Jump(nnn))
     }
 
-    pub fn op_2nnn(&mut self, nnn: u16) -> Action {
+    pub fn op_2nnn(&mut self, nnn: u16) -> OpResult {
         let mut memory = self.memory.lock().unwrap();
+        if self.sp as usize >= memory.stack.len() {
+            return Err(Chip8Error::StackOverflow { addr: self.pc });
+        }
         memory.stack[self.sp as usize] = self.pc;
         self.sp += 1;
-        Action::Jump(nnn)
+        Ok(Action::Jump(nnn))
     }
 
-    pub fn op_3xkk(&mut self, x: usize, kk: u8) -> Action {
+    pub fn op_3xkk(&mut self, x: usize, kk: u8) -> OpResult {
         if self.v[x] == kk {
-            Action::Skip
+            Ok(Action::Skip)
         } else {
-            Action::Next
+            Ok(Action::Next)
         }
     }
 
-    pub fn op_4xkk(&mut self, x: usize, kk: u8) -> Action {
+    pub fn op_4xkk(&mut self, x: usize, kk: u8) -> OpResult {
         if self.v[x] != kk {
-            Action::Skip
+            Ok(Action::Skip)
         } else {
-            Action::Next
+            Ok(Action::Next)
         }
     }
 
-    pub fn op_5xy0(&mut self, x: usize, y: usize) -> Action {
+    pub fn op_5xy0(&mut self, x: usize, y: usize) -> OpResult {
         if self.v[x] == self.v[y] {
-            Action::Skip
+            Ok(Action::Skip)
         } else {
-            Action::Next
+            Ok(Action::Next)
         }
     }
 
-    pub fn op_6xkk(&mut self, x: usize, kk: u8) -> Action {
+    pub fn op_5xy2(&mut self, x: usize, y: usize) -> OpResult {
+        let mut memory = self.memory.lock().unwrap();
+        for (offset, r) in register_range(x, y).enumerate() {
+            memory.write(self.i as usize + offset, self.v[r])?;
+        }
+        Ok(Action::Next)
+    }
+
+    pub fn op_5xy3(&mut self, x: usize, y: usize) -> OpResult {
+        let memory = self.memory.lock().unwrap();
+        for (offset, r) in register_range(x, y).enumerate() {
+            self.v[r] = memory.read(self.i as usize + offset)?;
+        }
+        Ok(Action::Next)
+    }
+
+    pub fn op_6xkk(&mut self, x: usize, kk: u8) -> OpResult {
         self.v[x] = kk;
-        Action::Next
+        Ok(Action::Next)
     }
 
-    pub fn op_7xkk(&mut self, x: usize, kk: u8) -> Action {
+    pub fn op_7xkk(&mut self, x: usize, kk: u8) -> OpResult {
         self.v[x] = self.v[x].wrapping_add(kk);
-        Action::Next
+        Ok(Action::Next)
     }
 
-    pub fn op_8xy0(&mut self, x: usize, y: usize) -> Action {
+    pub fn op_8xy0(&mut self, x: usize, y: usize) -> OpResult {
         self.v[x] = self.v[y];
-        Action::Next
+        Ok(Action::Next)
     }
 
-    pub fn op_8xy1(&mut self, x: usize, y: usize) -> Action {
-        self.v[x] = self.v[x] | self.v[y];
-        Action::Next
+    pub fn op_8xy1(&mut self, x: usize, y: usize) -> OpResult {
+        self.v[x] |= self.v[y];
+        if self.quirks.logic_resets_vf {
+            self.v[0xF] = 0;
+        }
+        Ok(Action::Next)
     }
 
-    pub fn op_8xy2(&mut self, x: usize, y: usize) -> Action {
-        self.v[x] = self.v[x] & self.v[y];
-        Action::Next
+    pub fn op_8xy2(&mut self, x: usize, y: usize) -> OpResult {
+        self.v[x] &= self.v[y];
+        if self.quirks.logic_resets_vf {
+            self.v[0xF] = 0;
+        }
+        Ok(Action::Next)
     }
 
-    pub fn op_8xy3(&mut self, x: usize, y: usize) -> Action {
-        self.v[x] = self.v[x] ^ self.v[y];
-        Action::Next
+    pub fn op_8xy3(&mut self, x: usize, y: usize) -> OpResult {
+        self.v[x] ^= self.v[y];
+        if self.quirks.logic_resets_vf {
+            self.v[0xF] = 0;
+        }
+        Ok(Action::Next)
     }
 
-    pub fn op_8xy4(&mut self, x: usize, y: usize) -> Action {
+    pub fn op_8xy4(&mut self, x: usize, y: usize) -> OpResult {
         let vx = self.v[x] as u16;
         let vy = self.v[y] as u16;
         let result = vx + vy;
         self.v[x] = self.v[x].wrapping_add(self.v[y]);
         self.v[0xF] = if result > 0xFF { 1 } else { 0 };
-        Action::Next
+        Ok(Action::Next)
     }
 
-    pub fn op_8xy5(&mut self, x: usize, y: usize) -> Action {
+    pub fn op_8xy5(&mut self, x: usize, y: usize) -> OpResult {
         self.v[0xF] = if self.v[x] > self.v[y] { 1 } else { 0 };
         self.v[x] = self.v[x].wrapping_sub(self.v[y]);
-        Action::Next
+        Ok(Action::Next)
     }
 
-    pub fn op_8xy6(&mut self, x: usize, _y: usize) -> Action {
-        self.v[0xF] = self.v[x] & 0x1;
-        self.v[x] >>= 1;
-        Action::Next
+    pub fn op_8xy6(&mut self, x: usize, y: usize) -> OpResult {
+        let value = if self.quirks.shift_uses_vy { self.v[y] } else { self.v[x] };
+        self.v[x] = value >> 1;
+        self.v[0xF] = value & 0x1;
+        Ok(Action::Next)
     }
 
-    pub fn op_8xy7(&mut self, x: usize, y: usize) -> Action {
+    pub fn op_8xy7(&mut self, x: usize, y: usize) -> OpResult {
         let vx = self.v[x];
         let vy = self.v[y];
         self.v[0xF] = if vy > vx { 1 } else { 0 };
         self.v[x] = self.v[x].wrapping_sub(self.v[y]);
-        Action::Next
+        Ok(Action::Next)
     }
 
-    pub fn op_8xye(&mut self, x: usize, _y: usize) -> Action {
-        self.v[0xF] = ((self.v[x] & 0x80) >> 7) & 0x1;
-        self.v[x] <<= 1;
-        Action::Next
+    pub fn op_8xye(&mut self, x: usize, y: usize) -> OpResult {
+        let value = if self.quirks.shift_uses_vy { self.v[y] } else { self.v[x] };
+        self.v[x] = value << 1;
+        self.v[0xF] = (value & 0x80) >> 7;
+        Ok(Action::Next)
     }
 
-    pub fn op_9xy0(&mut self, x: usize, y: usize) -> Action {
+    pub fn op_9xy0(&mut self, x: usize, y: usize) -> OpResult {
         if self.v[x] != self.v[y] {
-            Action::Skip
+            Ok(Action::Skip)
         } else {
-            Action::Next
+            Ok(Action::Next)
         }
     }
 
-    pub fn op_annn(&mut self, nnn: u16) -> Action {
+    pub fn op_annn(&mut self, nnn: u16) -> OpResult {
         self.i = nnn;
-        Action::Next
+        Ok(Action::Next)
     }
 
-    pub fn op_bnnn(&mut self, nnn: u16) -> Action {
-        Action::Jump(self.v[0x0] as u16 + nnn)
+    pub fn op_bnnn(&mut self, nnn: u16) -> OpResult {
+        let x = if self.quirks.jump_uses_vx { (nnn >> 8) as usize } else { 0x0 };
+        Ok(Action::Jump(self.v[x] as u16 + nnn))
     }
 
-    pub fn op_cxkk(&mut self, x: usize, kk: u8) -> Action {
-        let mut rng = rand::thread_rng();
-        let rnd = rng.gen::<u8>();
-        self.v[x] = (rnd as u8 & kk) as u8;
-        Action::Next
+    pub fn op_cxkk(&mut self, x: usize, kk: u8) -> OpResult {
+        let rnd = self.rng.next_byte();
+        self.v[x] = rnd & kk;
+        Ok(Action::Next)
     }
 
-    pub fn op_dxyn(&mut self, x: usize, y: usize, n: usize) -> Action {
+    /// Draws an 8xN sprite, or a 16x16 one when `n` is zero (SUPER-CHIP), on
+    /// every selected bitplane. Each plane reads its own copy of the sprite,
+    /// stored one after the other starting at I (XO-CHIP).
+    pub fn op_dxyn(&mut self, x: usize, y: usize, n: usize) -> OpResult {
         let mut memory = self.memory.lock().unwrap();
+        let width = memory.width();
+        let height = memory.height();
+        let (rows, cols) = if n == 0 { (16, 16) } else { (n, 8) };
+        let origin_x = self.v[x] as usize % width;
+        let origin_y = self.v[y] as usize % height;
+        let wrap = self.quirks.wrap_sprites;
+        let planes = self.plane;
+        let mut sprite = self.i as usize;
         self.v[0xF] = 0;
-        println!("I: {:?}", self.i);
-        for byte in 0..n as usize {
-            let coord_y = (self.v[y] as usize + byte) % CHIP8_HEIGHT;
-            for bit in 0..8 {
-                let coord_x = (self.v[x].wrapping_add(bit)) as usize % CHIP8_WIDTH;
-                let color = (memory.ram[self.i as usize + byte] >> (7 - bit)) & 1;
-                self.v[0xF] |= color & memory.vram[coord_y][coord_x];
-                print!("{:?}", color ^ memory.vram[coord_y][coord_x]);
-                memory.vram[coord_y][coord_x] = color ^ memory.vram[coord_y][coord_x];
+        for plane in [0x1, 0x2].iter().filter(|&&plane| planes & plane != 0) {
+            for row in 0..rows {
+                if !wrap && origin_y + row >= height {
+                    break;
+                }
+                let coord_y = (origin_y + row) % height;
+                for col in 0..cols {
+                    if !wrap && origin_x + col >= width {
+                        break;
+                    }
+                    let coord_x = (origin_x + col) % width;
+                    let byte = memory.read(sprite + row * cols / 8 + col / 8)?;
+                    if (byte >> (7 - col % 8)) & 1 == 1 {
+                        if memory.vram[coord_y][coord_x] & plane != 0 {
+                            self.v[0xF] = 1;
+                        }
+                        memory.vram[coord_y][coord_x] ^= plane;
+                        memory.mark_dirty(coord_y);
+                    }
+                }
             }
-            println!("");
+            sprite += rows * cols / 8;
         }
-        Action::Next
+        Ok(Action::Next)
     }
 
-    pub fn op_ex9e<K>(&mut self, x: usize, keyboard: &K) -> Action
+    /// Only the low nibble of Vx picks the key, as on the VIP.
+    pub fn op_ex9e<K>(&mut self, x: usize, keyboard: &K) -> OpResult
         where K: KeyboardDriver
     {
-        if keyboard.is_key_pressed(self.v[x]) {
-            Action::Skip
+        if keyboard.is_key_pressed(self.v[x] & 0xF) {
+            Ok(Action::Skip)
         } else {
-            Action::Next
+            Ok(Action::Next)
         }
     }
 
-    pub fn op_exa1<K>(&mut self, x: usize, keyboard: &K) -> Action
+    pub fn op_exa1<K>(&mut self, x: usize, keyboard: &K) -> OpResult
         where K: KeyboardDriver
     {
-        if !keyboard.is_key_pressed(self.v[x]) {
-            Action::Skip
+        if !keyboard.is_key_pressed(self.v[x] & 0xF) {
+            Ok(Action::Skip)
         } else {
-            Action::Next
+            Ok(Action::Next)
         }
     }
 
-    pub fn op_fx07(&mut self, x: usize) -> Action {
+    pub fn op_f000(&mut self) -> OpResult {
+        self.i = self.word_at(self.pc.wrapping_add(OPCODE_SIZE));
+        Ok(Action::Jump(self.pc.wrapping_add(2 * OPCODE_SIZE)))
+    }
+
+    pub fn op_fn01(&mut self, n: usize) -> OpResult {
+        self.plane = n as u8 & 0x3;
+        Ok(Action::Next)
+    }
+
+    pub fn op_f002(&mut self) -> OpResult {
+        let memory = self.memory.lock().unwrap();
+        let i = self.i as usize;
+        let mut pattern = [0; 16];
+        pattern.copy_from_slice(memory.slice(i, 16)?);
+        self.audio_pattern = Some(pattern);
+        Ok(Action::Next)
+    }
+
+    pub fn op_fx07(&mut self, x: usize) -> OpResult {
         self.v[x] = self.delay_timer;
-        Action::Next
+        Ok(Action::Next)
     }
 
-    pub fn op_fx0a(&mut self, x: usize) -> Action {
+    pub fn op_fx0a(&mut self, x: usize) -> OpResult {
         self.keypad_waiting = true;
         self.keypad_register = x as u8;
-        Action::Next
+        self.keypad_key = None;
+        Ok(Action::Next)
     }
 
-    pub fn op_fx15(&mut self, x: usize) -> Action {
+    pub fn op_fx15(&mut self, x: usize) -> OpResult {
         self.delay_timer = self.v[x];
-        Action::Next
+        Ok(Action::Next)
     }
 
-    pub fn op_fx18(&mut self, x: usize) -> Action {
+    pub fn op_fx18(&mut self, x: usize) -> OpResult {
         self.sound_timer = self.v[x];
-        Action::Next
+        Ok(Action::Next)
     }
 
-    pub fn op_fx1e(&mut self, x: usize) -> Action {
+    pub fn op_fx1e(&mut self, x: usize) -> OpResult {
         self.i = self.i.wrapping_add(self.v[x].into());
-        Action::Next
+        Ok(Action::Next)
     }
 
-    pub fn op_fx29(&mut self, x: usize) -> Action {
+    pub fn op_fx29(&mut self, x: usize) -> OpResult {
         self.i = ((self.v[x] as usize) * 5) as u16;
-        Action::Next
+        Ok(Action::Next)
     }
 
-    pub fn op_fx33(&mut self, x: usize) -> Action {
+    pub fn op_fx30(&mut self, x: usize) -> OpResult {
+        self.i = (BIG_FONT_ADDR + (self.v[x] as usize & 0xF) * 10) as u16;
+        Ok(Action::Next)
+    }
+
+    pub fn op_fx33(&mut self, x: usize) -> OpResult {
         let mut memory = self.memory.lock().unwrap();
-        memory.ram[self.i as usize] = self.v[x] / 100;
-        memory.ram[(self.i + 1) as usize] = (self.v[x] / 10) % 10;
-        memory.ram[(self.i + 2) as usize] = (self.v[x] % 100) % 10;
-        Action::Next
+        let digits = memory.slice_mut(self.i as usize, 3)?;
+        digits[0] = self.v[x] / 100;
+        digits[1] = (self.v[x] / 10) % 10;
+        digits[2] = self.v[x] % 10;
+        Ok(Action::Next)
     }
 
-    pub fn op_fx55(&mut self, x: usize) -> Action {
+    pub fn op_fx3a(&mut self, x: usize) -> OpResult {
+        self.pitch = self.v[x];
+        Ok(Action::Next)
+    }
+
+    pub fn op_fx55(&mut self, x: usize) -> OpResult {
         let mut memory = self.memory.lock().unwrap();
-        for i in 0..(x as u16 + 1) {
-            memory.ram[(self.i + i) as usize] = self.v[i as usize];
+        let i = self.i as usize;
+        memory.slice_mut(i, x + 1)?.copy_from_slice(&self.v[..=x]);
+        if self.quirks.load_store_increments_i {
+            self.i = self.i.wrapping_add(x as u16 + 1);
+        } else if self.quirks.load_store_increments_i_by_x {
+            self.i = self.i.wrapping_add(x as u16);
         }
-        Action::Next
+        Ok(Action::Next)
     }
 
-    pub fn op_fx65(&mut self, x: usize) -> Action {
+    pub fn op_fx65(&mut self, x: usize) -> OpResult {
         let memory = self.memory.lock().unwrap();
-        for i in 0..(x as u16 + 1) {
-            self.v[i as usize] = memory.ram[(self.i + i) as usize];
+        let i = self.i as usize;
+        self.v[..=x].copy_from_slice(memory.slice(i, x + 1)?);
+        if self.quirks.load_store_increments_i {
+            self.i = self.i.wrapping_add(x as u16 + 1);
+        } else if self.quirks.load_store_increments_i_by_x {
+            self.i = self.i.wrapping_add(x as u16);
+        }
+        Ok(Action::Next)
+    }
+
+    pub fn op_fx75(&mut self, x: usize) -> OpResult {
+        self.rpl[..=x].copy_from_slice(&self.v[..=x]);
+        Ok(Action::Next)
+    }
+
+    pub fn op_fx85(&mut self, x: usize) -> OpResult {
+        self.v[..=x].copy_from_slice(&self.rpl[..=x]);
+        Ok(Action::Next)
+    }
+}
+
+/// Registers touched by `5xy2`/`5xy3`, in order from x to y (either direction).
+fn register_range(x: usize, y: usize) -> Box<dyn Iterator<Item = usize>> {
+    if x <= y {
+        Box::new(x..=y)
+    } else {
+        Box::new((y..=x).rev())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use machine::Machine;
+    use drivers::{Keyboard, NullAudio};
+
+    type TestMachine = Machine<NullAudio, Keyboard>;
+
+    fn machine(quirks: Quirks, program: &[u16]) -> TestMachine {
+        let bytes: Vec<u8> = program.iter().flat_map(|word| word.to_be_bytes().to_vec()).collect();
+        let mut machine = Machine::new(NullAudio, Keyboard::new(), quirks);
+        machine.load_program(&bytes).unwrap();
+        machine
+    }
+
+    /// Runs `program` for one frame with only the named quirks turned on.
+    /// Programs end on the zeroed memory after them, which halts.
+    fn run(quirks: &[&str], program: &[u16]) -> TestMachine {
+        let mut machine = machine(Quirks::from_enabled(quirks).unwrap(), program);
+        machine.frame().unwrap();
+        machine
+    }
+
+    fn registers(machine: &TestMachine) -> ([u8; 16], u16, u16) {
+        let cpu = machine.cpu.lock().unwrap();
+        (cpu.v, cpu.i, cpu.pc)
+    }
+
+    #[test]
+    fn shift_uses_vy() {
+        let program = [0x6005, 0x610C, 0x8016, 0x6281, 0x8326];
+        let (v, _, _) = registers(&run(&["shift_uses_vy"], &program));
+        assert_eq!((v[0], v[3], v[0xF]), (0x06, 0x40, 1));
+        let (v, _, _) = registers(&run(&[], &program));
+        assert_eq!((v[0], v[3], v[0xF]), (0x02, 0x00, 0));
+    }
+
+    #[test]
+    fn load_store_increments_i() {
+        let program = [0xA300, 0x6207, 0xF255, 0xF265];
+        let (_, i, _) = registers(&run(&["load_store_increments_i"], &program));
+        assert_eq!(i, 0x306);
+        let (_, i, _) = registers(&run(&[], &program));
+        assert_eq!(i, 0x300);
+    }
+
+    #[test]
+    fn load_store_increments_i_by_x() {
+        let program = [0xA300, 0x6207, 0xF255, 0xF265];
+        let machine = run(&["load_store_increments_i_by_x"], &program);
+        let (v, i, _) = registers(&machine);
+        assert_eq!((v[0], v[2], i), (0x07, 0x00, 0x304));
+        assert_eq!(&machine.memory.lock().unwrap().ram[0x300..0x303], &[0, 0, 7]);
+        let (_, i, _) = registers(&run(&["load_store_increments_i", "load_store_increments_i_by_x"], &program));
+        assert_eq!(i, 0x306);
+    }
+
+    #[test]
+    fn jump_uses_vx() {
+        let program = [0x6001, 0x6210, 0xB210];
+        let (_, _, pc) = registers(&run(&["jump_uses_vx"], &program));
+        assert_eq!(pc, 0x220);
+        let (_, _, pc) = registers(&run(&[], &program));
+        assert_eq!(pc, 0x211);
+    }
+
+    #[test]
+    fn logic_resets_vf() {
+        for &opcode in [0x8011, 0x8012, 0x8013].iter() {
+            let program = [0x6F05, 0x6003, 0x6106, opcode];
+            let (v, _, _) = registers(&run(&["logic_resets_vf"], &program));
+            assert_eq!(v[0xF], 0, "{:04X}", opcode);
+            let (v, _, _) = registers(&run(&[], &program));
+            assert_eq!(v[0xF], 5, "{:04X}", opcode);
         }
-        Action::Next
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn wrap_sprites() {
+        // The font's "0" at (62, 30): four columns and five rows.
+        let program = [0x603E, 0x611E, 0xA000, 0xD015];
+        let machine = run(&["wrap_sprites"], &program);
+        let memory = machine.memory.lock().unwrap();
+        assert_eq!((memory.vram[30][63], memory.vram[30][0], memory.vram[0][1]), (1, 1, 1));
+        let machine = run(&[], &program);
+        let memory = machine.memory.lock().unwrap();
+        assert_eq!((memory.vram[30][63], memory.vram[30][0], memory.vram[0][1]), (1, 0, 0));
+    }
+
+    #[test]
+    fn key_wait_on_release() {
+        let program = [0xF30A];
+        let mut on_release = machine(Quirks::from_enabled(&["key_wait_on_release"]).unwrap(), &program);
+        on_release.press_key(0x7);
+        on_release.frame().unwrap();
+        assert!(on_release.cpu.lock().unwrap().keypad_waiting);
+        on_release.release_key(0x7);
+        on_release.frame().unwrap();
+        assert!(!on_release.cpu.lock().unwrap().keypad_waiting);
+        assert_eq!(registers(&on_release).0[3], 0x7);
+
+        let mut held = machine(Quirks::from_enabled(&[]).unwrap(), &program);
+        held.press_key(0x7);
+        held.frame().unwrap();
+        assert!(!held.cpu.lock().unwrap().keypad_waiting);
+        assert_eq!(registers(&held).0[3], 0x7);
+    }
+
+    #[test]
+    fn scrolls_the_screen() {
+        let scrolled = |opcode: u16| {
+            let mut machine = machine(Quirks::default(), &[opcode]);
+            {
+                let mut memory = machine.memory.lock().unwrap();
+                memory.vram[10][20] = 1;
+                memory.vram[31][63] = 1;
+            }
+            machine.frame().unwrap();
+            let memory = machine.memory.lock().unwrap();
+            (0..memory.height())
+                .flat_map(|y| (0..memory.width()).map(move |x| (x, y)))
+                .filter(|&(x, y)| memory.vram[y][x] != 0)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(scrolled(0x00C3), vec![(20, 13)]);
+        assert_eq!(scrolled(0x00D2), vec![(20, 8), (63, 29)]);
+        assert_eq!(scrolled(0x00FB), vec![(24, 10)]);
+        assert_eq!(scrolled(0x00FC), vec![(16, 10), (59, 31)]);
+    }
+
+    #[test]
+    fn switches_resolution_and_draws_16x16_sprites() {
+        let program = [0x00FF, 0xA300, 0x6005, 0x6103, 0xD010];
+        let mut hires = machine(Quirks::default(), &program);
+        {
+            let mut memory = hires.memory.lock().unwrap();
+            memory.ram[0x300..0x302].copy_from_slice(&[0xFF, 0xFF]);
+            memory.ram[0x31E..0x320].copy_from_slice(&[0x00, 0x01]);
+        }
+        hires.frame().unwrap();
+        {
+            let memory = hires.memory.lock().unwrap();
+            assert_eq!((memory.width(), memory.height()), (128, 64));
+            assert!(memory.vram[3][5..21].iter().all(|&pixel| pixel == 1));
+            assert_eq!((memory.vram[3][4], memory.vram[3][21]), (0, 0));
+            assert_eq!(&memory.vram[18][19..22], &[0, 1, 0]);
+            assert_eq!(memory.vram.iter().flatten().filter(|&&pixel| pixel != 0).count(), 17);
+        }
+        assert_eq!(registers(&hires).0[0xF], 0);
+
+        let mut lores = machine(Quirks::default(), &[0x00FF, 0x00FE]);
+        lores.frame().unwrap();
+        let memory = lores.memory.lock().unwrap();
+        assert_eq!((memory.width(), memory.height()), (64, 32));
+    }
+
+    #[test]
+    fn skips_both_words_of_a_long_load() {
+        let (v, i, _) = registers(&run(&[], &[0x6001, 0x3001, 0xF000, 0x0400, 0x6105]));
+        assert_eq!((v[1], i), (0x05, 0x000));
+        let (v, i, _) = registers(&run(&[], &[0x6001, 0x3002, 0xF000, 0x0400, 0x6105]));
+        assert_eq!((v[1], i), (0x05, 0x400));
+    }
+
+    #[test]
+    fn draws_on_the_selected_planes() {
+        let program = [0xF201, 0xA300, 0xD001, 0xF301, 0xA310, 0x6101, 0xD011];
+        let mut machine = machine(Quirks::default(), &program);
+        {
+            let mut memory = machine.memory.lock().unwrap();
+            memory.ram[0x300] = 0x80;
+            memory.ram[0x310..0x312].copy_from_slice(&[0xC0, 0x60]);
+        }
+        machine.frame().unwrap();
+        let memory = machine.memory.lock().unwrap();
+        assert_eq!(&memory.vram[0][..3], &[2, 0, 0]);
+        assert_eq!(&memory.vram[1][..4], &[1, 3, 2, 0]);
+    }
+
+    #[test]
+    fn saves_and_loads_register_ranges_in_either_direction() {
+        let program = [0x6001, 0x6102, 0x6203, 0xA300, 0x5202, 0xA310, 0x5313];
+        let mut machine = machine(Quirks::default(), &program);
+        machine.memory.lock().unwrap().ram[0x310..0x313].copy_from_slice(&[0xA, 0xB, 0xC]);
+        machine.frame().unwrap();
+        assert_eq!(&machine.memory.lock().unwrap().ram[0x300..0x304], &[3, 2, 1, 0]);
+        let (v, i, _) = registers(&machine);
+        assert_eq!((&v[..5], i), (&[1, 0xC, 0xB, 0xA, 0][..], 0x310));
+    }
+
+    #[test]
+    fn clears_only_the_selected_planes() {
+        for &(plane, left) in [(0xF101, 2), (0xF201, 1), (0xF301, 0)].iter() {
+            let mut machine = machine(Quirks::default(), &[plane, 0x00E0]);
+            machine.memory.lock().unwrap().vram[5][5] = 3;
+            machine.frame().unwrap();
+            assert_eq!(machine.memory.lock().unwrap().vram[5][5], left, "{:04X}", plane);
+        }
+    }
+
+    #[test]
+    fn skips_on_the_low_nibble_of_out_of_range_keys() {
+        let program = [0x60FF, 0xE09E, 0x6101, 0xE0A1, 0x6201];
+        let mut machine = machine(Quirks::default(), &program);
+        machine.press_key(0xF);
+        machine.frame().unwrap();
+        let (v, _, _) = registers(&machine);
+        assert_eq!((v[1], v[2]), (0, 1));
+
+        let (v, _, _) = registers(&run(&[], &program));
+        assert_eq!((v[1], v[2]), (1, 0));
+    }
+}
@@ -1,22 +1,196 @@
 use cpu::Cpu;
+use instruction::{decode, Instruction};
 use memory::Memory;
+use machine::{MEMORY_SIZE, OPCODE_SIZE};
+use std::fmt;
+use std::io::{self, BufRead};
 use std::sync::{Arc, Mutex};
-use piston::input::{Button, Key};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
 use hardware::KeyboardDriver;
 
-#[derive(PartialEq, Debug)]
-pub enum DebugMode {
-    Disabled,
-    Step,
-    CpuInfo,
-    OpcodeInfo
+pub const DEBUG_PROMPT: &str = "(chip8) ";
+pub const DEFAULT_EXAMINE_BYTES: usize = 16;
+pub const DEFAULT_DISASSEMBLE_LINES: usize = 10;
+
+/// Usage lines printed by `help`.
+pub const DEBUG_COMMANDS: [(&str, &str); 12] = [
+    ("break [ADDR]", "pause now, or whenever PC reaches ADDR"),
+    ("delete [ADDR]", "remove the breakpoint at ADDR, or all of them"),
+    ("continue", "resume running"),
+    ("step [N]", "run N instructions (default 1), into subroutines"),
+    ("next", "run one instruction, over subroutine calls"),
+    ("finish", "run until the current subroutine returns"),
+    ("regs", "show the registers"),
+    ("x/N ADDR", "dump N bytes of memory (default 16)"),
+    ("disas [ADDR] [N]", "disassemble N instructions from ADDR (default PC)"),
+    ("set REG = VALUE", "set v0-vf, i, pc, sp, dt, st or *ADDR"),
+    ("trace [on|off]", "print every instruction as it runs"),
+    ("help", "show this list"),
+];
+
+/// A debugger command, typed at the console or bound to a key. Addresses and
+/// values are decimal, or hex with a `0x` prefix.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DebugCommand {
+    /// Sets a breakpoint, or pauses right away without an address.
+    Break(Option<u16>),
+    Delete(Option<u16>),
+    Continue,
+    /// Runs this many instructions, following calls.
+    Step(usize),
+    Next,
+    Finish,
+    Registers,
+    /// Dumps this many bytes from an address.
+    Examine(usize, u16),
+    /// Disassembles this many instructions from an address, or from PC.
+    Disassemble(Option<u16>, usize),
+    Set(DebugTarget, u16),
+    /// Turns tracing on or off, or toggles it.
+    Trace(Option<bool>),
+    Help,
+}
+
+/// What `set` can change.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DebugTarget {
+    V(usize),
+    I,
+    Pc,
+    Sp,
+    DelayTimer,
+    SoundTimer,
+    Memory(u16),
 }
 
+impl DebugCommand {
+    /// Parses a command line, accepting gdb's short forms (`b`, `c`, `s`,
+    /// `n`, ...). The names of the old debugger key bindings are still
+    /// understood so existing configs keep working.
+    pub fn parse(line: &str) -> Result<Self, String> {
+        let mut words = line.split_whitespace();
+        let word = words.next().unwrap_or("");
+        let args: Vec<&str> = words.collect();
+        let mut parts = word.splitn(2, '/');
+        let name = parts.next().unwrap_or("");
+        let format = parts.next();
+        if format.is_some() && name != "x" {
+            return Err(format!("'{}' takes no /format", name));
+        }
+
+        let command = match (name, args.as_slice()) {
+            ("b", args) | ("break", args) => DebugCommand::Break(optional_number(args)?),
+            ("d", args) | ("delete", args) => DebugCommand::Delete(optional_number(args)?),
+            ("c", []) | ("continue", []) | ("disable", []) => DebugCommand::Continue,
+            ("s", args) | ("si", args) | ("step", args) | ("stepi", args) => {
+                DebugCommand::Step(optional_number(args)?.map_or(1, |count| count as usize))
+            }
+            ("n", []) | ("ni", []) | ("next", []) | ("nexti", []) => DebugCommand::Next,
+            ("fin", []) | ("finish", []) => DebugCommand::Finish,
+            ("regs", []) | ("registers", []) | ("info", ["registers"]) => DebugCommand::Registers,
+            ("x", [addr]) => {
+                let count = match format {
+                    Some(count) => number(count)? as usize,
+                    None => DEFAULT_EXAMINE_BYTES,
+                };
+                DebugCommand::Examine(count, number(addr)?)
+            }
+            ("disas", args) | ("disassemble", args) => match *args {
+                [] => DebugCommand::Disassemble(None, DEFAULT_DISASSEMBLE_LINES),
+                [addr] => DebugCommand::Disassemble(Some(number(addr)?), DEFAULT_DISASSEMBLE_LINES),
+                [addr, count] => DebugCommand::Disassemble(Some(number(addr)?), number(count)? as usize),
+                _ => return Err(String::from("usage: disas [ADDR] [N]")),
+            },
+            ("set", args) => {
+                let assignment = args.join(" ").replacen('=', " ", 1);
+                match *assignment.split_whitespace().collect::<Vec<_>>() {
+                    [target, value] => DebugCommand::Set(DebugTarget::parse(target)?, number(value)?),
+                    _ => return Err(String::from("usage: set REG = VALUE")),
+                }
+            }
+            ("trace", []) => DebugCommand::Trace(None),
+            ("trace", ["on"]) => DebugCommand::Trace(Some(true)),
+            ("trace", ["off"]) => DebugCommand::Trace(Some(false)),
+            ("h", []) | ("help", []) | ("?", []) => DebugCommand::Help,
+            ("step_mode", []) => DebugCommand::Break(None),
+            ("cpu_info_mode", []) | ("opcode_info_mode", []) => DebugCommand::Trace(Some(true)),
+            ("step_info", []) => DebugCommand::Step(1),
+            ("dump_memory", []) => DebugCommand::Examine(MEMORY_SIZE, 0),
+            ("", _) => return Err(String::from("no command given")),
+            _ => return Err(format!("unknown command '{}', try help", line.trim())),
+        };
+        Ok(command)
+    }
+
+    /// Whether the command runs instructions outside the machine's frames,
+    /// changes its state, or can stop it partway through a frame.
+    pub fn alters_execution(&self) -> bool {
+        match *self {
+            DebugCommand::Break(addr) => addr.is_some(),
+            DebugCommand::Step(_) | DebugCommand::Next | DebugCommand::Finish | DebugCommand::Set(..) => true,
+            _ => false,
+        }
+    }
+}
+
+impl DebugTarget {
+    fn parse(name: &str) -> Result<Self, String> {
+        let name = name.trim_start_matches('$').to_ascii_lowercase();
+        let target = match name.as_str() {
+            "i" => DebugTarget::I,
+            "pc" => DebugTarget::Pc,
+            "sp" => DebugTarget::Sp,
+            "dt" => DebugTarget::DelayTimer,
+            "st" => DebugTarget::SoundTimer,
+            _ if name.starts_with('*') => DebugTarget::Memory(number(&name[1..])?),
+            _ if name.len() == 2 && name.starts_with('v') => {
+                let x = usize::from_str_radix(&name[1..], 16).map_err(|_| format!("unknown register '{}'", name))?;
+                DebugTarget::V(x)
+            }
+            _ => return Err(format!("unknown register '{}', expected v0-vf, i, pc, sp, dt, st or *ADDR", name)),
+        };
+        Ok(target)
+    }
+}
+
+impl fmt::Display for DebugTarget {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DebugTarget::V(x) => write!(f, "v{:x}", x),
+            DebugTarget::I => write!(f, "i"),
+            DebugTarget::Pc => write!(f, "pc"),
+            DebugTarget::Sp => write!(f, "sp"),
+            DebugTarget::DelayTimer => write!(f, "dt"),
+            DebugTarget::SoundTimer => write!(f, "st"),
+            DebugTarget::Memory(addr) => write!(f, "*0x{:03X}", addr),
+        }
+    }
+}
+
+/// Where a `next` or `finish` is running to: the stack must be no deeper
+/// than `depth` and, for `next`, PC back at `return_to`.
+#[derive(Clone, Copy)]
+struct Until {
+    return_to: Option<u16>,
+    depth: u8,
+}
+
+/// Breakpoints and stepping for a running machine. Commands come from the
+/// console or key bindings through `execute`; while running, the machine
+/// calls `check` before each instruction so the debugger can pause it.
 pub struct Debugger {
     pub cpu: Arc<Mutex<Cpu>>,
     pub memory: Arc<Mutex<Memory>>,
-    pub program: Vec<u8>,
-    pub mode: DebugMode,
+    pub paused: bool,
+    pub trace: bool,
+    pub breakpoints: Vec<u16>,
+    /// Why the machine last paused on its own, for the frontend to show.
+    pub stopped: Option<String>,
+    until: Option<Until>,
+    /// Lets the first instruction after resuming run even if it is a
+    /// breakpoint.
+    resuming: bool,
 }
 
 impl Debugger {
@@ -24,171 +198,289 @@ impl Debugger {
         Debugger {
             cpu,
             memory,
-            program: Vec::new(),
-            mode: DebugMode::Disabled
+            paused: false,
+            trace: false,
+            breakpoints: Vec::new(),
+            stopped: None,
+            until: None,
+            resuming: false,
         }
     }
 
-    pub fn debug(&self, cpu: &Cpu, opcode: u16) {
-        match self.mode {
-            DebugMode::Step => {
-                // let cpu = self.cpu.lock().unwrap();
-                println!("{:?} - 0x{:X}: {:?}", cpu.steps, cpu.pc, Self::describe_opcode(opcode));
-            }
-            
-            DebugMode::CpuInfo => {
-                // let cpu = self.cpu.lock().unwrap();
-                // print!("{}[2J", 27 as char);
-                println!("======================================================");
-                println!("ADDR: 0x{:X} | OPCODE: 0x{:X}: {:?}", cpu.pc, opcode, Self::describe_opcode(opcode));
-                for i in 0..16 {
-                    println!("V{:?}: 0x{:X} ({:?})", i, cpu.v[i], cpu.v[i]);
-                }
-                println!("I: 0x{:X} ({:?})", cpu.i, cpu.i);
-                println!("PC: 0x{:X} ({:?})", cpu.pc, cpu.pc);
-                println!("SP: 0x{:X} ({:?})", cpu.sp, cpu.sp);
-                println!("DT: 0x{:X} ({:?})", cpu.delay_timer, cpu.delay_timer);
-            }
+    /// Prints an instruction about to run, while tracing.
+    pub fn trace_instruction(&self, cpu: &Cpu, opcode: u16) {
+        println!("{:?} - 0x{:03X}: {}", cpu.steps, cpu.pc, decode(opcode));
+    }
 
-            _ => {} // TODO: Finish this
-        }               
+    /// Pauses the machine, recording `reason` for the frontend.
+    pub fn pause(&mut self, reason: String) {
+        self.paused = true;
+        self.until = None;
+        self.stopped = Some(reason);
     }
 
-    pub fn input_key<K>(&mut self, key: &Button, keyboard: &K)
-        where K: KeyboardDriver + Sync + Send
-    {
-        match key {
-            &Button::Keyboard(Key::D0) => {
-                self.mode = DebugMode::Disabled;
-                println!("DEBUG MODE: {:?}", self.mode);
-            }
+    /// Records that the program halted the machine, for the frontend.
+    pub fn halted(&mut self, cpu: &Cpu) {
+        self.stopped = Some(format!("halted at {}", location(cpu)));
+    }
 
-            &Button::Keyboard(Key::D9) => {
-                self.mode = DebugMode::Step;
-                println!("DEBUG MODE: {:?}", self.mode);
-            }
+    /// Called before each instruction while running. Returns whether to
+    /// stop, having paused at a breakpoint or where `next` or `finish` were
+    /// headed.
+    pub fn check(&mut self, cpu: &Cpu) -> bool {
+        if self.resuming {
+            self.resuming = false;
+            return false;
+        }
+        let arrived = self.until.is_some_and(|until| {
+            cpu.sp <= until.depth && until.return_to.is_none_or(|pc| cpu.pc == pc)
+        });
+        let reason = if self.breakpoints.contains(&cpu.pc) {
+            "breakpoint"
+        } else if arrived {
+            "stopped"
+        } else {
+            return false;
+        };
+        self.pause(format!("{} at {}", reason, location(cpu)));
+        true
+    }
 
-            &Button::Keyboard(Key::D8) => {
-                self.mode = DebugMode::CpuInfo;
-                println!("DEBUG MODE: {:?}", self.mode);
+    pub fn execute<K>(&mut self, command: DebugCommand, keyboard: &mut K) -> String
+        where K: KeyboardDriver
+    {
+        match command {
+            DebugCommand::Break(None) => {
+                self.paused = true;
+                self.until = None;
+                format!("paused at {}", location(&self.cpu.lock().unwrap()))
             }
-
-            &Button::Keyboard(Key::D7) => {
-                self.mode = DebugMode::OpcodeInfo;
-                println!("DEBUG MODE: {:?}", self.mode);
+            DebugCommand::Break(Some(addr)) => {
+                if !self.breakpoints.contains(&addr) {
+                    self.breakpoints.push(addr);
+                }
+                format!("breakpoint at 0x{:03X}", addr)
             }
-
-            &Button::Keyboard(Key::N) => {
-                if self.mode == DebugMode::Step {
-                    let mut cpu = self.cpu.lock().unwrap();
-                    cpu.tick(keyboard, &self);
+            DebugCommand::Delete(Some(addr)) => {
+                let count = self.breakpoints.len();
+                self.breakpoints.retain(|&breakpoint| breakpoint != addr);
+                if self.breakpoints.len() < count {
+                    format!("deleted breakpoint at 0x{:03X}", addr)
+                } else {
+                    format!("no breakpoint at 0x{:03X}", addr)
                 }
             }
-
-            &Button::Keyboard(Key::I) => {
-                if self.mode == DebugMode::Step {
-                    let mut cpu = self.cpu.lock().unwrap();
-                    cpu.tick(keyboard, &self);
-                    println!("======================================================");
-                    for i in 0..16 {
-                        println!("V{:?}: 0x{:X} ({:?})", i, cpu.v[i], cpu.v[i]);
+            DebugCommand::Delete(None) => {
+                self.breakpoints.clear();
+                String::from("deleted all breakpoints")
+            }
+            DebugCommand::Continue => {
+                self.resume(None);
+                String::from("continuing")
+            }
+            DebugCommand::Step(count) => self.step(count, keyboard),
+            DebugCommand::Next => {
+                let (opcode, pc, sp) = {
+                    let cpu = self.cpu.lock().unwrap();
+                    (cpu.opcode(), cpu.pc, cpu.sp)
+                };
+                match decode(opcode) {
+                    Instruction::Call(_) => {
+                        let return_to = pc.wrapping_add(OPCODE_SIZE);
+                        self.resume(Some(Until { return_to: Some(return_to), depth: sp }));
+                        format!("running to 0x{:03X}", return_to)
                     }
-                    println!("I: 0x{:X} ({:?})", cpu.i, cpu.i);
-                    println!("PC: 0x{:X} ({:?})", cpu.pc, cpu.pc);
-                    println!("SP: 0x{:X} ({:?})", cpu.sp, cpu.sp);
-                    println!("DT: 0x{:X} ({:?})", cpu.delay_timer, cpu.delay_timer);
+                    _ => self.step(1, keyboard),
                 }
             }
-
-            &Button::Keyboard(Key::L) => {
-                if self.mode == DebugMode::Step {
-                    let mut cpu = self.cpu.lock().unwrap();
-                    for i in 0..16 {
-                        println!("V{:?}: 0x{:X} ({:?})", i, cpu.v[i], cpu.v[i]);
-                    }
-                    println!("I: 0x{:X} ({:?})", cpu.i, cpu.i);
-                    println!("PC: 0x{:X} ({:?})", cpu.pc, cpu.pc);
-                    println!("SP: 0x{:X} ({:?})", cpu.sp, cpu.sp);
+            DebugCommand::Finish => {
+                let sp = self.cpu.lock().unwrap().sp;
+                if sp == 0 {
+                    return String::from("not in a subroutine");
                 }
+                self.resume(Some(Until { return_to: None, depth: sp - 1 }));
+                String::from("running until return")
             }
-
-            &Button::Keyboard(Key::M) => {
-                if self.mode == DebugMode::Step {
-                    let memory = self.memory.lock().unwrap();
-                    for i in 0..memory.ram.len() {
-                        println!("0x{:X}: {:X}", i, memory.ram[i]);
-                    }
+            DebugCommand::Registers => registers(&self.cpu.lock().unwrap()),
+            DebugCommand::Examine(count, addr) => {
+                let memory = self.memory.lock().unwrap();
+                let start = (addr as usize).min(memory.ram.len());
+                let end = (start + count).min(memory.ram.len());
+                let lines: Vec<String> = memory.ram[start..end].chunks(16).enumerate()
+                    .map(|(row, bytes)| {
+                        let bytes: Vec<String> = bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+                        format!("0x{:04X}: {}", start + row * 16, bytes.join(" "))
+                    })
+                    .collect();
+                lines.join("\n")
+            }
+            DebugCommand::Disassemble(addr, count) => {
+                let cpu = self.cpu.lock().unwrap();
+                let mut addr = addr.unwrap_or(cpu.pc);
+                let mut lines = Vec::with_capacity(count);
+                for _ in 0..count {
+                    let marker = if addr == cpu.pc { "=>" } else { "  " };
+                    let opcode = cpu.word_at(addr);
+                    let (text, size) = match decode(opcode) {
+                        Instruction::LdILong => {
+                            (format!("LD I, 0x{:04X}", cpu.word_at(addr.wrapping_add(OPCODE_SIZE))), 2 * OPCODE_SIZE)
+                        }
+                        instruction => (instruction.to_string(), OPCODE_SIZE),
+                    };
+                    lines.push(format!("{} 0x{:03X}: {:04X}  {}", marker, addr, opcode, text));
+                    addr = addr.wrapping_add(size);
                 }
+                lines.join("\n")
+            }
+            DebugCommand::Set(target, value) => self.set(target, value),
+            DebugCommand::Trace(on) => {
+                self.trace = on.unwrap_or(!self.trace);
+                format!("trace {}", if self.trace { "on" } else { "off" })
+            }
+            DebugCommand::Help => {
+                let lines: Vec<String> = DEBUG_COMMANDS.iter()
+                    .map(|&(usage, description)| format!("{:<18}{}", usage, description))
+                    .collect();
+                lines.join("\n")
+            }
+        }
+    }
+
+    fn resume(&mut self, until: Option<Until>) {
+        self.paused = false;
+        self.until = until;
+        self.resuming = true;
+    }
+
+    /// Runs `count` instructions with the machine paused, stopping early at a
+    /// breakpoint or fault.
+    fn step<K>(&mut self, count: usize, keyboard: &mut K) -> String
+        where K: KeyboardDriver
+    {
+        self.paused = true;
+        self.until = None;
+        let mut cpu = self.cpu.lock().unwrap();
+        for done in 0..count {
+            if done > 0 && self.breakpoints.contains(&cpu.pc) {
+                return format!("breakpoint at {}", location(&cpu));
             }
+            if let Err(error) = cpu.tick(keyboard, self) {
+                return error.to_string();
+            }
+        }
+        location(&cpu)
+    }
 
-            &Button::Keyboard(Key::O) => {
-                if self.mode == DebugMode::Step {
-                    let program = &self.program;
-                    for addr in 0..program.len() {
-                        let offset = 0x200;
-                        if (addr + offset) & 1 == 0 && addr + 1 < program.len()  {
-                            let opcode = (program[addr] as u16) << 8 | (program[addr + 1] as u16);
-                            println!("0x{:X}: {:?}", 0x200 + addr, Self::describe_opcode(opcode));
-                        } 
-                    }
+    fn set(&mut self, target: DebugTarget, value: u16) -> String {
+        let mut cpu = self.cpu.lock().unwrap();
+        let byte = match target {
+            DebugTarget::I | DebugTarget::Pc => 0,
+            _ if value > 0xFF => return format!("{} holds a byte, at most 0xFF", target),
+            _ => value as u8,
+        };
+        match target {
+            DebugTarget::V(x) => cpu.v[x] = byte,
+            DebugTarget::I => cpu.i = value,
+            DebugTarget::Pc => cpu.pc = value,
+            DebugTarget::Sp => {
+                let depth = self.memory.lock().unwrap().stack.len();
+                if byte as usize > depth {
+                    return format!("sp must be at most {}", depth);
+                }
+                cpu.sp = byte;
+            }
+            DebugTarget::DelayTimer => cpu.delay_timer = byte,
+            DebugTarget::SoundTimer => cpu.sound_timer = byte,
+            DebugTarget::Memory(addr) => {
+                if let Err(error) = self.memory.lock().unwrap().write(addr as usize, byte) {
+                    return error.to_string();
                 }
             }
+        }
+        format!("{} = 0x{:X}", target, value)
+    }
+}
 
-            _ => {}
+/// Reads debugger commands from stdin on a thread of its own, so the frontend
+/// can poll for them between frames without blocking.
+pub fn spawn_console() -> Receiver<String> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let stdin = io::stdin();
+        for line in stdin.lock().lines().map_while(Result::ok) {
+            if sender.send(line).is_err() {
+                break;
+            }
         }
+    });
+    receiver
+}
+
+fn location(cpu: &Cpu) -> String {
+    format!("0x{:03X}: {}", cpu.pc, decode(cpu.opcode()))
+}
+
+fn registers(cpu: &Cpu) -> String {
+    let mut lines: Vec<String> = cpu.v.chunks(4).enumerate()
+        .map(|(row, registers)| {
+            let cells: Vec<String> = registers.iter().enumerate()
+                .map(|(i, value)| format!("V{:X} 0x{:02X}", row * 4 + i, value))
+                .collect();
+            cells.join("  ")
+        })
+        .collect();
+    lines.push(format!("I  0x{:03X}  PC 0x{:03X}  SP {}", cpu.i, cpu.pc, cpu.sp));
+    lines.push(format!("DT 0x{:02X}  ST 0x{:02X}", cpu.delay_timer, cpu.sound_timer));
+    lines.join("\n")
+}
+
+fn number(text: &str) -> Result<u16, String> {
+    let parsed = if text.starts_with("0x") || text.starts_with("0X") {
+        u16::from_str_radix(&text[2..], 16)
+    } else {
+        text.parse()
+    };
+    parsed.map_err(|_| format!("invalid number '{}'", text))
+}
+
+fn optional_number(args: &[&str]) -> Result<Option<u16>, String> {
+    match *args {
+        [] => Ok(None),
+        [arg] => number(arg).map(Some),
+        _ => Err(String::from("too many arguments")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use machine::Machine;
+    use drivers::{Keyboard, NullAudio};
+    use quirks::Quirks;
+
+    fn run(machine: &mut Machine<NullAudio, Keyboard>, line: &str) -> String {
+        machine.debug(DebugCommand::parse(line).unwrap())
     }
 
-    pub fn describe_opcode(opcode: u16) -> String {
-        let nibbles = (
-            ((opcode & 0xF000) >> 12) as u8,
-            ((opcode & 0x0F00) >> 8) as u8,
-            ((opcode & 0x00F0) >> 4) as u8,
-            (opcode & 0x000F) as u8,
-        );
-
-        let nnn = (opcode & 0x0FFF) as u16;
-        let kk = (opcode & 0x00FF) as u8;
-        let x = nibbles.1 as usize;
-        let y = nibbles.2 as usize;
-        let n = nibbles.3 as usize;
-
-        match nibbles {
-            (0x0, 0x0, 0x0, 0x0)    => String::from("HALT"),
-            (0x0, 0x0, 0xE, 0x0)    => String::from("CLS"),
-            (0x0, 0x0, 0xE, 0xE)    => String::from("RET"),
-            (0x1, _, _, _)          => format!("JP 0x{:X} ({:?})", nnn, nnn),           // JP addr
-            (0x2, _, _, _)          => format!("CALL 0x{:X} ({:?})", nnn, nnn),           // CALL addr
-            (0x3, _, _, _)          => format!("SE V{:?} 0x{:X} ({:?})", x, kk, kk),         // SE Vx, byte
-            (0x4, _, _, _)          => format!("SNE V{:?} 0x{:X} ({:?})", x, kk, kk),         // SNE Vx, byte
-            (0x5, _, _, 0x00)       => format!("SE V{:?} V{:?}", x, y),          // SE Vx, Vy
-            (0x6, _, _, _)          => format!("LD V{:?} 0x{:X} ({:?})", x, kk, kk),         // LD Vx, byte
-            (0x7, _, _, _)          => format!("ADD V{:?} 0x{:X} ({:?})", x, kk, kk),         // ADD Vx, byte
-            (0x8, _, _, 0x00)       => format!("LD V{:?} V{:?}", x, y),          // LD Vx, Vy
-            (0x8, _, _, 0x01)       => format!("OR V{:?} V{:?}", x, y),          // OR Vx, Vy
-            (0x8, _, _, 0x02)       => format!("AND V{:?} V{:?}", x, y),          // AND Vx, Vy
-            (0x8, _, _, 0x03)       => format!("XOR V{:?} V{:?}", x, y),          // XOR Vx, Vy
-            (0x8, _, _, 0x04)       => format!("ADD V{:?} V{:?}", x, y),          // ADD Vx, Vy
-            (0x8, _, _, 0x05)       => format!("SUB V{:?} V{:?}", x, y),          // SUB Vx, Vy
-            (0x8, _, _, 0x06)       => format!("SHR V{:?} [, V{:?}]", x, y),          // SHR Vx {, Vy}
-            (0x8, _, _, 0x07)       => format!("SUBN V{:?} V{:?}", x, y),          // SUBN Vx, Vy
-            (0x8, _, _, 0x0E)       => format!("SHL V{:?} [, V{:?}]", x, y),          // SHL Vx {, Vy}
-            (0x9, _, _, 0x00)       => format!("SNE V{:?}, V{:?}", x, y),          // SNE Vx, Vy
-            (0xA, _, _, _)          => format!("LDI, 0x{:X} ({:?})", nnn, nnn),           // LDI, addr
-            (0xB, _, _, _)          => format!("JP V0, {:?} ({:?})", nnn, nnn),           // JP V0, addr
-            (0xC, _, _, _)          => format!("RND V{:?}, 0x{:X} ({:?})", x, kk, kk),         // RND Vx, byte
-            (0xD, _, _, _)          => format!("DRW V{:?}, V{:?}, 0x{:X} ({:X})", x, y, n, n),       // DRW Vx, Vy, nibble
-            (0xE, _, 0x09, 0x0E)    => format!("SKP V{:?}", x),   // SKP Vx
-            (0xE, _, 0x0A, 0x01)    => format!("SKNP V{:?}", x),   // SKNP Vx
-            (0xF, _, 0x00, 0x07)    => format!("LD V{:?}, DT", x),         // LD Vx, DT
-            (0xF, _, 0x00, 0x0A)    => format!("LD V{:?}, K", x),         // LD Vx, K
-            (0xF, _, 0x01, 0x05)    => format!("LD DT, V{:?}", x),         // LD DT, Vx
-            (0xF, _, 0x01, 0x08)    => format!("LD ST, V{:?}", x),         // LD ST, Vx
-            (0xF, _, 0x01, 0x0E)    => format!("ADD I, V{:?}", x),         // ADD I, Vx
-            (0xF, _, 0x02, 0x09)    => format!("LD F, V{:?}", x),         // LD F, Vx
-            (0xF, _, 0x03, 0x03)    => format!("LD B, V{:?}", x),         // LD B, Vx
-            (0xF, _, 0x05, 0x05)    => format!("LD [I], V{:?}", x),         // LD [I], Vx
-            (0xF, _, 0x06, 0x05)    => format!("LD V{:?}, [I]", x),         // LD Vx, [I]
-            _ => format!("0x{:X}", opcode)
+    #[test]
+    fn sets_registers_and_memory() {
+        let mut machine = Machine::new(NullAudio, Keyboard::new(), Quirks::default());
+        assert_eq!(run(&mut machine, "set v3 = 0x12"), "v3 = 0x12");
+        assert_eq!(run(&mut machine, "set pc 0x300"), "pc = 0x300");
+        assert_eq!(run(&mut machine, "set *0x400=255"), "*0x400 = 0xFF");
+        let cpu = machine.cpu.lock().unwrap();
+        assert_eq!((cpu.v[3], cpu.pc), (0x12, 0x300));
+        assert_eq!(machine.memory.lock().unwrap().ram[0x400], 0xFF);
+    }
+
+    #[test]
+    fn rejects_values_too_large_for_bytes() {
+        let mut machine = Machine::new(NullAudio, Keyboard::new(), Quirks::default());
+        let before = machine.save_state();
+        for line in ["set v3 = 300", "set dt = 0x100", "set st 256", "set *0x300 = 0x1FF", "set sp 17", "set sp 272"].iter() {
+            assert!(!run(&mut machine, line).contains(" = "), "{}", line);
         }
+        assert_eq!(machine.save_state(), before);
+        assert_eq!(run(&mut machine, "set i = 0xFFF"), "i = 0xFFF");
     }
-}
\ No newline at end of file
+}
@@ -1,4 +1,81 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
 use hardware::AudioDriver;
 
-pub struct Audio {}
-impl AudioDriver for Audio {}
\ No newline at end of file
+/// Discards all sound.
+pub struct NullAudio;
+
+impl AudioDriver for NullAudio {
+    fn queue(&mut self, _samples: &[i16]) {}
+}
+
+/// Writes everything played to a 16-bit mono WAV file, for listening to a
+/// session afterwards or checking audio without a sound card. The header is
+/// completed by `finish`, which also runs when the writer is dropped.
+pub struct WavWriter {
+    file: BufWriter<File>,
+    data_len: u32,
+    finished: bool,
+}
+
+impl WavWriter {
+    pub fn create<P: AsRef<Path>>(path: P, sample_rate: u32) -> io::Result<Self> {
+        let mut file = BufWriter::new(File::create(path)?);
+        file.write_all(b"RIFF")?;
+        file.write_all(&36u32.to_le_bytes())?;
+        file.write_all(b"WAVEfmt ")?;
+        file.write_all(&16u32.to_le_bytes())?;
+        file.write_all(&1u16.to_le_bytes())?; // PCM
+        file.write_all(&1u16.to_le_bytes())?; // mono
+        file.write_all(&sample_rate.to_le_bytes())?;
+        file.write_all(&(sample_rate * 2).to_le_bytes())?; // bytes per second
+        file.write_all(&2u16.to_le_bytes())?; // bytes per sample
+        file.write_all(&16u16.to_le_bytes())?; // bits per sample
+        file.write_all(b"data")?;
+        file.write_all(&0u32.to_le_bytes())?;
+
+        Ok(WavWriter {
+            file,
+            data_len: 0,
+            finished: false,
+        })
+    }
+
+    /// Fills in the chunk sizes left open by `create`.
+    pub fn finish(&mut self) -> io::Result<()> {
+        if self.finished {
+            return Ok(());
+        }
+        self.finished = true;
+        self.file.seek(SeekFrom::Start(4))?;
+        self.file.write_all(&(36 + self.data_len).to_le_bytes())?;
+        self.file.seek(SeekFrom::Start(40))?;
+        self.file.write_all(&self.data_len.to_le_bytes())?;
+        self.file.flush()
+    }
+}
+
+impl AudioDriver for WavWriter {
+    fn queue(&mut self, samples: &[i16]) {
+        if self.finished {
+            return;
+        }
+        for sample in samples {
+            if let Err(error) = self.file.write_all(&sample.to_le_bytes()) {
+                eprintln!("failed to write audio: {}", error);
+                self.finished = true;
+                return;
+            }
+        }
+        self.data_len += samples.len() as u32 * 2;
+    }
+}
+
+impl Drop for WavWriter {
+    fn drop(&mut self) {
+        if let Err(error) = self.finish() {
+            eprintln!("failed to finish audio: {}", error);
+        }
+    }
+}
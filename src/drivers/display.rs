@@ -0,0 +1,89 @@
+use hardware::DisplayDriver;
+use palette::Palette;
+use phosphor::Phosphor;
+
+/// Discards every frame, for running headless.
+pub struct NullDisplay;
+
+impl DisplayDriver for NullDisplay {
+    fn refresh(&mut self, _vram: &[Vec<u8>], _dirty: &[bool]) {}
+}
+
+/// Keeps the framebuffer as packed RGBA bytes, row by row, for tests and for
+/// encoders that want plain images.
+pub struct RgbaDisplay {
+    pub width: usize,
+    pub height: usize,
+    pub palette: Palette,
+    pub phosphor: Phosphor,
+    pub pixels: Vec<u8>,
+}
+
+impl RgbaDisplay {
+    pub fn new() -> Self {
+        RgbaDisplay {
+            width: 0,
+            height: 0,
+            palette: Palette::default(),
+            phosphor: Phosphor::default(),
+            pixels: Vec::new(),
+        }
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> [u8; 4] {
+        let offset = (y * self.width + x) * 4;
+        let mut rgba = [0; 4];
+        rgba.copy_from_slice(&self.pixels[offset..offset + 4]);
+        rgba
+    }
+}
+
+impl Default for RgbaDisplay {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DisplayDriver for RgbaDisplay {
+    fn refresh(&mut self, vram: &[Vec<u8>], dirty: &[bool]) {
+        if !self.phosphor.update(vram, dirty, &self.palette) {
+            return;
+        }
+        self.height = vram.len();
+        self.width = vram.first().map_or(0, |row| row.len());
+        self.pixels.clear();
+        for color in self.phosphor.colors() {
+            self.pixels.extend_from_slice(color);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use machine::Machine;
+    use drivers::{Keyboard, NullAudio};
+    use quirks::Quirks;
+
+    #[test]
+    fn rgba_display_colours_each_plane() {
+        // The font's "0" on the first plane at (0, 0) and on the second at (8, 0).
+        let program = [0xA0, 0x00, 0xD0, 0x05, 0x61, 0x08, 0xF2, 0x01, 0xD1, 0x05];
+        let mut machine = Machine::new(NullAudio, Keyboard::new(), Quirks::default());
+        machine.load_program(&program).unwrap();
+        machine.frame().unwrap();
+
+        let palette = Palette::from_name("contrast").unwrap();
+        let mut display = RgbaDisplay::new();
+        display.palette = palette;
+        machine.present(&mut display);
+
+        assert_eq!((display.width, display.height), (64, 32));
+        assert_eq!(display.pixels.len(), 64 * 32 * 4);
+        assert_eq!(display.pixel(0, 0), palette.colors[1]);
+        assert_eq!(display.pixel(3, 4), palette.colors[1]);
+        assert_eq!(display.pixel(1, 1), palette.colors[0]);
+        assert_eq!(display.pixel(8, 0), palette.colors[2]);
+        assert_eq!(display.pixel(63, 31), palette.colors[0]);
+    }
+}
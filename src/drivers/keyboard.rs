@@ -1,17 +1,27 @@
 use hardware::KeyboardDriver;
 
 pub struct Keyboard {
-    keys: [bool; 16]
+    keys: [bool; 16],
+    pressed: [bool; 16],
+    released: [bool; 16],
 }
 
 impl Keyboard {
     pub fn new() -> Self {
         Keyboard {
-            keys: [false; 16]
+            keys: [false; 16],
+            pressed: [false; 16],
+            released: [false; 16],
         }
     }
 }
 
+impl Default for Keyboard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl KeyboardDriver for Keyboard {
     fn is_key_pressed(&self, key: u8) -> bool {
         self.keys[key as usize]
@@ -28,12 +38,183 @@ impl KeyboardDriver for Keyboard {
     }
 
     fn press(&mut self, key: u8) {
+        if !self.keys[key as usize] {
+            self.pressed[key as usize] = true;
+        }
         self.keys[key as usize] = true;
-
     }
 
     fn release(&mut self, key: u8) {
+        if self.keys[key as usize] {
+            self.released[key as usize] = true;
+        }
         self.keys[key as usize] = false;
+    }
+
+    fn take_press(&mut self) -> Option<u8> {
+        let key = self.pressed.iter().position(|&pressed| pressed)?;
+        self.pressed[key] = false;
+        Some(key as u8)
+    }
+
+    fn take_release(&mut self, key: u8) -> bool {
+        let released = self.released[key as usize];
+        self.released[key as usize] = false;
+        released
+    }
+
+    fn clear_edges(&mut self) {
+        self.pressed = [false; 16];
+        self.released = [false; 16];
+    }
+}
+
+/// Frames a key stays down after the terminal last reported it. Terminals
+/// only send key presses (repeated while a key is held), never releases.
+pub const TERMINAL_HOLD_FRAMES: u8 = 8;
+
+/// Keypad fed from a terminal, which cannot report releases. Every press
+/// holds the key for `TERMINAL_HOLD_FRAMES` frames and key repeat keeps it
+/// held; `expire` reports the keys that should now be let go.
+pub struct TerminalKeyboard {
+    keys: Keyboard,
+    hold: [u8; 16],
+}
+
+impl TerminalKeyboard {
+    pub fn new() -> Self {
+        TerminalKeyboard {
+            keys: Keyboard::new(),
+            hold: [0; 16],
+        }
+    }
+
+    /// Counts the holds down by one frame, returning the keys that ran out.
+    pub fn expire(&mut self) -> Vec<u8> {
+        let mut expired = Vec::new();
+        for (key, hold) in self.hold.iter_mut().enumerate() {
+            if *hold > 0 {
+                *hold -= 1;
+                if *hold == 0 {
+                    expired.push(key as u8);
+                }
+            }
+        }
+        expired
+    }
+}
+
+impl Default for TerminalKeyboard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl KeyboardDriver for TerminalKeyboard {
+    fn is_key_pressed(&self, key: u8) -> bool {
+        self.keys.is_key_pressed(key)
+    }
 
+    fn get_key(&self) -> Option<u8> {
+        self.keys.get_key()
+    }
+
+    fn press(&mut self, key: u8) {
+        self.hold[key as usize] = TERMINAL_HOLD_FRAMES;
+        self.keys.press(key);
+    }
+
+    fn release(&mut self, key: u8) {
+        self.hold[key as usize] = 0;
+        self.keys.release(key);
+    }
+
+    fn take_press(&mut self) -> Option<u8> {
+        self.keys.take_press()
+    }
+
+    fn take_release(&mut self, key: u8) -> bool {
+        self.keys.take_release(key)
+    }
+
+    fn clear_edges(&mut self) {
+        self.keys.clear_edges();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use machine::Machine;
+    use drivers::NullAudio;
+    use quirks::Quirks;
+
+    /// One instruction per frame of `6000 F30A`, so the wait starts on the
+    /// second frame.
+    fn waiting(quirks: Quirks) -> Machine<NullAudio, Keyboard> {
+        let mut machine = Machine::new(NullAudio, Keyboard::new(), quirks);
+        machine.load_program(&[0x60, 0x00, 0xF3, 0x0A]).unwrap();
+        machine.instructions_per_frame = 1;
+        machine
+    }
+
+    fn waited(machine: &Machine<NullAudio, Keyboard>) -> Option<u8> {
+        let cpu = machine.cpu.lock().unwrap();
+        if cpu.keypad_waiting { None } else { Some(cpu.v[3]) }
+    }
+
+    #[test]
+    fn held_key_ends_the_wait_when_released() {
+        let mut machine = waiting(Quirks::vip());
+        machine.press_key(0x4);
+        for _ in 0..4 {
+            machine.frame().unwrap();
+        }
+        assert_eq!(waited(&machine), None);
+        machine.release_key(0x4);
+        machine.frame().unwrap();
+        assert_eq!(waited(&machine), Some(0x4));
+    }
+
+    #[test]
+    fn tap_between_frames_ends_the_wait() {
+        let mut machine = waiting(Quirks::vip());
+        machine.frame().unwrap();
+        machine.frame().unwrap();
+        assert_eq!(waited(&machine), None);
+        machine.press_key(0x9);
+        machine.release_key(0x9);
+        machine.frame().unwrap();
+        assert_eq!(waited(&machine), Some(0x9));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn held_key_is_taken_at_once_without_the_quirk() {
+        let mut quirks = Quirks::vip();
+        quirks.key_wait_on_release = false;
+        let mut machine = waiting(quirks);
+        machine.press_key(0x4);
+        for _ in 0..3 {
+            machine.frame().unwrap();
+        }
+        assert_eq!(waited(&machine), Some(0x4));
+    }
+
+    #[test]
+    fn unused_presses_are_dropped_each_frame() {
+        let mut machine = waiting(Quirks::vip());
+        machine.press_key(0x2);
+        machine.release_key(0x2);
+        for _ in 0..3 {
+            machine.frame().unwrap();
+        }
+        assert_eq!(waited(&machine), None);
+
+        let mut keyboard = Keyboard::new();
+        keyboard.press(0x2);
+        keyboard.release(0x2);
+        keyboard.clear_edges();
+        assert_eq!(keyboard.take_press(), None);
+        assert!(!keyboard.take_release(0x2));
+    }
+}
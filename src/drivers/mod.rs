@@ -1,5 +1,9 @@
 mod audio;
+mod display;
 mod keyboard;
+mod random;
 
 pub use self::audio::*;
-pub use self::keyboard::*;
\ No newline at end of file
+pub use self::display::*;
+pub use self::keyboard::*;
+pub use self::random::*;
@@ -0,0 +1,110 @@
+use hardware::RandomDriver;
+
+pub const RANDOM_DRIVERS: [&str; 1] = ["xorshift"];
+
+/// Builds the random source registered under `name`, seeded with `seed`.
+pub fn random_driver(name: &str, seed: u64) -> Option<Box<dyn RandomDriver + Send>> {
+    match name {
+        "xorshift" => Some(Box::new(XorShiftRandom::new(seed))),
+        _ => None,
+    }
+}
+
+/// General purpose generator, the default source for `Cxkk`. This is the
+/// xorshift128 of `rand`'s `XorShiftRng`, seeded the same way, kept here so
+/// its state can be saved.
+pub struct XorShiftRandom {
+    state: [u32; 4],
+}
+
+impl XorShiftRandom {
+    pub fn new(seed: u64) -> Self {
+        let mut xorshift = XorShiftRandom { state: [0; 4] };
+        xorshift.reseed(seed);
+        xorshift
+    }
+}
+
+impl RandomDriver for XorShiftRandom {
+    fn name(&self) -> &'static str {
+        "xorshift"
+    }
+
+    fn next_byte(&mut self) -> u8 {
+        let [x, y, z, w] = self.state;
+        let t = x ^ (x << 11);
+        let next = w ^ (w >> 19) ^ t ^ (t >> 8);
+        self.state = [y, z, w, next];
+        next as u8
+    }
+
+    fn reseed(&mut self, seed: u64) {
+        self.state = [seed as u32, (seed >> 32) as u32, !seed as u32, (!seed >> 32) as u32];
+    }
+
+    fn state(&self) -> Vec<u8> {
+        self.state.iter().flat_map(|word| word.to_le_bytes().to_vec()).collect()
+    }
+
+    fn set_state(&mut self, state: &[u8]) -> bool {
+        if state.len() != 16 || state.iter().all(|&byte| byte == 0) {
+            return false;
+        }
+        for (word, bytes) in self.state.iter_mut().zip(state.chunks(4)) {
+            *word = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{Rng, SeedableRng};
+    use rand::prng::XorShiftRng;
+
+    fn seeded_rng(seed: u64) -> XorShiftRng {
+        let mut bytes = [0; 16];
+        bytes[..8].copy_from_slice(&seed.to_le_bytes());
+        bytes[8..].copy_from_slice(&(!seed).to_le_bytes());
+        XorShiftRng::from_seed(bytes)
+    }
+
+    #[test]
+    fn xorshift_matches_rand() {
+        for &seed in [0, 1, 0xDEAD_BEEF, u64::MAX].iter() {
+            let mut ours = XorShiftRandom::new(seed);
+            let mut theirs = seeded_rng(seed);
+            for _ in 0..100 {
+                assert_eq!(ours.next_byte(), theirs.gen::<u8>());
+            }
+        }
+    }
+
+    #[test]
+    fn sequences_depend_only_on_the_seed() {
+        for name in RANDOM_DRIVERS.iter() {
+            let mut first = random_driver(name, 2024).unwrap();
+            let mut second = random_driver(name, 1).unwrap();
+            second.reseed(2024);
+            let bytes: Vec<u8> = (0..64).map(|_| first.next_byte()).collect();
+            assert_eq!(bytes, (0..64).map(|_| second.next_byte()).collect::<Vec<u8>>(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn state_resumes_the_sequence() {
+        for name in RANDOM_DRIVERS.iter() {
+            let mut rng = random_driver(name, 99).unwrap();
+            rng.next_byte();
+            let state = rng.state();
+            let expected: Vec<u8> = (0..32).map(|_| rng.next_byte()).collect();
+
+            let mut resumed = random_driver(name, 1).unwrap();
+            assert!(resumed.set_state(&state));
+            let bytes: Vec<u8> = (0..32).map(|_| resumed.next_byte()).collect();
+            assert_eq!(bytes, expected, "{}", name);
+            assert!(!resumed.set_state(&state[1..]));
+        }
+    }
+}
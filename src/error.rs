@@ -0,0 +1,46 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+/// Faults raised by the interpreter and the program loader. The `u16`
+/// addresses of the opcode and stack faults are the value of PC when the fault
+/// happened, so the host can point the debugger at the offending instruction.
+/// `MemoryOutOfBounds` instead holds the memory address that was accessed.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Chip8Error {
+    UnknownOpcode { opcode: u16, addr: u16 },
+    StackOverflow { addr: u16 },
+    StackUnderflow { addr: u16 },
+    MemoryOutOfBounds { addr: usize },
+    RomTooLarge { size: usize, max: usize },
+    InvalidSaveState(String),
+    InvalidMovie(String),
+    InvalidConfig(String),
+    MovieActive,
+    Io(String),
+}
+
+impl fmt::Display for Chip8Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Chip8Error::UnknownOpcode { opcode, addr } => write!(f, "unknown opcode 0x{:04X} at 0x{:X}", opcode, addr),
+            Chip8Error::StackOverflow { addr } => write!(f, "stack overflow at 0x{:X}", addr),
+            Chip8Error::StackUnderflow { addr } => write!(f, "stack underflow at 0x{:X}", addr),
+            Chip8Error::MemoryOutOfBounds { addr } => write!(f, "memory access out of bounds at 0x{:X}", addr),
+            Chip8Error::RomTooLarge { size, max } => write!(f, "ROM is {} bytes but at most {} fit in memory", size, max),
+            Chip8Error::InvalidSaveState(ref reason) => write!(f, "invalid save state: {}", reason),
+            Chip8Error::InvalidMovie(ref reason) => write!(f, "invalid movie: {}", reason),
+            Chip8Error::InvalidConfig(ref reason) => write!(f, "invalid config: {}", reason),
+            Chip8Error::MovieActive => write!(f, "not possible while a movie is recorded or played"),
+            Chip8Error::Io(ref message) => write!(f, "i/o error: {}", message),
+        }
+    }
+}
+
+impl Error for Chip8Error {}
+
+impl From<io::Error> for Chip8Error {
+    fn from(error: io::Error) -> Self {
+        Chip8Error::Io(error.to_string())
+    }
+}
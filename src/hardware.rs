@@ -1,5 +1,12 @@
+/// Receives the sound for each 60 Hz frame as signed 16-bit mono samples.
 pub trait AudioDriver {
+    fn queue(&mut self, samples: &[i16]);
+}
 
+impl<T: AudioDriver + ?Sized> AudioDriver for Box<T> {
+    fn queue(&mut self, samples: &[i16]) {
+        (**self).queue(samples)
+    }
 }
 
 pub trait KeyboardDriver {
@@ -7,4 +14,34 @@ pub trait KeyboardDriver {
     fn get_key(&self) -> Option<u8>;
     fn press(&mut self, key: u8);
     fn release(&mut self, key: u8);
-}
\ No newline at end of file
+    /// Returns a key pressed since the edges were last cleared, consuming
+    /// that press.
+    fn take_press(&mut self) -> Option<u8>;
+    /// Whether `key` was released since the edges were last cleared,
+    /// consuming that release.
+    fn take_release(&mut self, key: u8) -> bool;
+    /// Forgets presses and releases nobody consumed. Called once per frame.
+    fn clear_edges(&mut self);
+}
+
+/// Receives the framebuffer once per presented frame. Each pixel holds its
+/// bitplane mask: bit 0 for the first plane and bit 1 for the second.
+/// `dirty` flags, for each row, whether it changed since the previous call;
+/// all are false when nothing was drawn.
+pub trait DisplayDriver {
+    fn refresh(&mut self, vram: &[Vec<u8>], dirty: &[bool]);
+}
+
+/// Source of the random bytes returned by `Cxkk`.
+pub trait RandomDriver {
+    /// The name `random_driver` builds this generator under.
+    fn name(&self) -> &'static str;
+    fn next_byte(&mut self) -> u8;
+    fn reseed(&mut self, seed: u64);
+    /// Where the generator is in its sequence, for save states.
+    fn state(&self) -> Vec<u8>;
+    /// Continues from a `state` taken from the same kind of generator.
+    /// Returns false, leaving the generator as it was, if `state` does not
+    /// fit it.
+    fn set_state(&mut self, state: &[u8]) -> bool;
+}
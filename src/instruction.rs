@@ -0,0 +1,308 @@
+use std::fmt;
+
+/// A decoded instruction. Register operands are register indices, addresses
+/// are 12 bit and bytes are immediate values, exactly as encoded.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Instruction {
+    Halt,                   // 0000
+    ScrollDown(u8),         // 00Cn     SCD nibble
+    ScrollUp(u8),           // 00Dn     SCU nibble
+    Cls,                    // 00E0     CLS
+    Ret,                    // 00EE     RET
+    ScrollRight,            // 00FB     SCR
+    ScrollLeft,             // 00FC     SCL
+    Exit,                   // 00FD     EXIT
+    Lores,                  // 00FE     LOW
+    Hires,                  // 00FF     HIGH
+    HiresCls,               // 0230     CLS (VIP hires)
+    Jp(u16),                // 1nnn     JP addr
+    Call(u16),              // 2nnn     CALL addr
+    SeByte(u8, u8),         // 3xkk     SE Vx, byte
+    SneByte(u8, u8),        // 4xkk     SNE Vx, byte
+    SeReg(u8, u8),          // 5xy0     SE Vx, Vy
+    SaveRange(u8, u8),      // 5xy2     LD [I], Vx-Vy
+    LoadRange(u8, u8),      // 5xy3     LD Vx-Vy, [I]
+    LdByte(u8, u8),         // 6xkk     LD Vx, byte
+    AddByte(u8, u8),        // 7xkk     ADD Vx, byte
+    LdReg(u8, u8),          // 8xy0     LD Vx, Vy
+    Or(u8, u8),             // 8xy1     OR Vx, Vy
+    And(u8, u8),            // 8xy2     AND Vx, Vy
+    Xor(u8, u8),            // 8xy3     XOR Vx, Vy
+    AddReg(u8, u8),         // 8xy4     ADD Vx, Vy
+    Sub(u8, u8),            // 8xy5     SUB Vx, Vy
+    Shr(u8, u8),            // 8xy6     SHR Vx {, Vy}
+    Subn(u8, u8),           // 8xy7     SUBN Vx, Vy
+    Shl(u8, u8),            // 8xyE     SHL Vx {, Vy}
+    SneReg(u8, u8),         // 9xy0     SNE Vx, Vy
+    LdI(u16),               // Annn     LD I, addr
+    JpV0(u16),              // Bnnn     JP V0, addr
+    Rnd(u8, u8),            // Cxkk     RND Vx, byte
+    Drw(u8, u8, u8),        // Dxyn     DRW Vx, Vy, nibble
+    Skp(u8),                // Ex9E     SKP Vx
+    Sknp(u8),               // ExA1     SKNP Vx
+    LdILong,                // F000     LD I, long (address in the next word)
+    Plane(u8),              // Fn01     PLANE n
+    Audio,                  // F002     AUDIO
+    LdVxDt(u8),             // Fx07     LD Vx, DT
+    LdVxK(u8),              // Fx0A     LD Vx, K
+    LdDtVx(u8),             // Fx15     LD DT, Vx
+    LdStVx(u8),             // Fx18     LD ST, Vx
+    AddIVx(u8),             // Fx1E     ADD I, Vx
+    LdF(u8),                // Fx29     LD F, Vx
+    LdHf(u8),               // Fx30     LD HF, Vx
+    LdB(u8),                // Fx33     LD B, Vx
+    Pitch(u8),              // Fx3A     PITCH Vx
+    Store(u8),              // Fx55     LD [I], Vx
+    Load(u8),               // Fx65     LD Vx, [I]
+    StoreRpl(u8),           // Fx75     LD R, Vx
+    LoadRpl(u8),            // Fx85     LD Vx, R
+    Unknown(u16),
+}
+
+pub fn decode(opcode: u16) -> Instruction {
+    use self::Instruction::*;
+
+    let nibbles = (
+        ((opcode & 0xF000) >> 12) as u8,
+        ((opcode & 0x0F00) >> 8) as u8,
+        ((opcode & 0x00F0) >> 4) as u8,
+        (opcode & 0x000F) as u8,
+    );
+
+    let nnn = opcode & 0x0FFF;
+    let kk = (opcode & 0x00FF) as u8;
+    let x = nibbles.1;
+    let y = nibbles.2;
+    let n = nibbles.3;
+
+    match nibbles {
+        (0x0, 0x0, 0x0, 0x0)    => Halt,
+        (0x0, 0x0, 0xC, _)      => ScrollDown(n),
+        (0x0, 0x0, 0xD, _)      => ScrollUp(n),
+        (0x0, 0x0, 0xE, 0x0)    => Cls,
+        (0x0, 0x0, 0xE, 0xE)    => Ret,
+        (0x0, 0x0, 0xF, 0xB)    => ScrollRight,
+        (0x0, 0x0, 0xF, 0xC)    => ScrollLeft,
+        (0x0, 0x0, 0xF, 0xD)    => Exit,
+        (0x0, 0x0, 0xF, 0xE)    => Lores,
+        (0x0, 0x0, 0xF, 0xF)    => Hires,
+        (0x0, 0x2, 0x3, 0x0)    => HiresCls,
+        (0x1, _, _, _)          => Jp(nnn),
+        (0x2, _, _, _)          => Call(nnn),
+        (0x3, _, _, _)          => SeByte(x, kk),
+        (0x4, _, _, _)          => SneByte(x, kk),
+        (0x5, _, _, 0x0)        => SeReg(x, y),
+        (0x5, _, _, 0x2)        => SaveRange(x, y),
+        (0x5, _, _, 0x3)        => LoadRange(x, y),
+        (0x6, _, _, _)          => LdByte(x, kk),
+        (0x7, _, _, _)          => AddByte(x, kk),
+        (0x8, _, _, 0x0)        => LdReg(x, y),
+        (0x8, _, _, 0x1)        => Or(x, y),
+        (0x8, _, _, 0x2)        => And(x, y),
+        (0x8, _, _, 0x3)        => Xor(x, y),
+        (0x8, _, _, 0x4)        => AddReg(x, y),
+        (0x8, _, _, 0x5)        => Sub(x, y),
+        (0x8, _, _, 0x6)        => Shr(x, y),
+        (0x8, _, _, 0x7)        => Subn(x, y),
+        (0x8, _, _, 0xE)        => Shl(x, y),
+        (0x9, _, _, 0x0)        => SneReg(x, y),
+        (0xA, _, _, _)          => LdI(nnn),
+        (0xB, _, _, _)          => JpV0(nnn),
+        (0xC, _, _, _)          => Rnd(x, kk),
+        (0xD, _, _, _)          => Drw(x, y, n),
+        (0xE, _, 0x9, 0xE)      => Skp(x),
+        (0xE, _, 0xA, 0x1)      => Sknp(x),
+        (0xF, 0x0, 0x0, 0x0)    => LdILong,
+        (0xF, _, 0x0, 0x1)      => Plane(x),
+        (0xF, 0x0, 0x0, 0x2)    => Audio,
+        (0xF, _, 0x0, 0x7)      => LdVxDt(x),
+        (0xF, _, 0x0, 0xA)      => LdVxK(x),
+        (0xF, _, 0x1, 0x5)      => LdDtVx(x),
+        (0xF, _, 0x1, 0x8)      => LdStVx(x),
+        (0xF, _, 0x1, 0xE)      => AddIVx(x),
+        (0xF, _, 0x2, 0x9)      => LdF(x),
+        (0xF, _, 0x3, 0x0)      => LdHf(x),
+        (0xF, _, 0x3, 0x3)      => LdB(x),
+        (0xF, _, 0x3, 0xA)      => Pitch(x),
+        (0xF, _, 0x5, 0x5)      => Store(x),
+        (0xF, _, 0x6, 0x5)      => Load(x),
+        (0xF, _, 0x7, 0x5)      => StoreRpl(x),
+        (0xF, _, 0x8, 0x5)      => LoadRpl(x),
+        _                       => Unknown(opcode),
+    }
+}
+
+pub fn encode(instruction: Instruction) -> u16 {
+    use self::Instruction::*;
+
+    let xkk = |high: u16, x: u8, kk: u8| high << 12 | (x as u16) << 8 | kk as u16;
+    let xyn = |high: u16, x: u8, y: u8, n: u8| high << 12 | (x as u16) << 8 | (y as u16) << 4 | n as u16;
+    let fx = |x: u8, low: u16| 0xF000 | (x as u16) << 8 | low;
+
+    match instruction {
+        Halt                => 0x0000,
+        ScrollDown(n)       => 0x00C0 | n as u16,
+        ScrollUp(n)         => 0x00D0 | n as u16,
+        Cls                 => 0x00E0,
+        Ret                 => 0x00EE,
+        ScrollRight         => 0x00FB,
+        ScrollLeft          => 0x00FC,
+        Exit                => 0x00FD,
+        Lores               => 0x00FE,
+        Hires               => 0x00FF,
+        HiresCls            => 0x0230,
+        Jp(nnn)             => 0x1000 | nnn,
+        Call(nnn)           => 0x2000 | nnn,
+        SeByte(x, kk)       => xkk(0x3, x, kk),
+        SneByte(x, kk)      => xkk(0x4, x, kk),
+        SeReg(x, y)         => xyn(0x5, x, y, 0x0),
+        SaveRange(x, y)     => xyn(0x5, x, y, 0x2),
+        LoadRange(x, y)     => xyn(0x5, x, y, 0x3),
+        LdByte(x, kk)       => xkk(0x6, x, kk),
+        AddByte(x, kk)      => xkk(0x7, x, kk),
+        LdReg(x, y)         => xyn(0x8, x, y, 0x0),
+        Or(x, y)            => xyn(0x8, x, y, 0x1),
+        And(x, y)           => xyn(0x8, x, y, 0x2),
+        Xor(x, y)           => xyn(0x8, x, y, 0x3),
+        AddReg(x, y)        => xyn(0x8, x, y, 0x4),
+        Sub(x, y)           => xyn(0x8, x, y, 0x5),
+        Shr(x, y)           => xyn(0x8, x, y, 0x6),
+        Subn(x, y)          => xyn(0x8, x, y, 0x7),
+        Shl(x, y)           => xyn(0x8, x, y, 0xE),
+        SneReg(x, y)        => xyn(0x9, x, y, 0x0),
+        LdI(nnn)            => 0xA000 | nnn,
+        JpV0(nnn)           => 0xB000 | nnn,
+        Rnd(x, kk)          => xkk(0xC, x, kk),
+        Drw(x, y, n)        => xyn(0xD, x, y, n),
+        Skp(x)              => xkk(0xE, x, 0x9E),
+        Sknp(x)             => xkk(0xE, x, 0xA1),
+        LdILong             => 0xF000,
+        Plane(n)            => fx(n, 0x01),
+        Audio               => 0xF002,
+        LdVxDt(x)           => fx(x, 0x07),
+        LdVxK(x)            => fx(x, 0x0A),
+        LdDtVx(x)           => fx(x, 0x15),
+        LdStVx(x)           => fx(x, 0x18),
+        AddIVx(x)           => fx(x, 0x1E),
+        LdF(x)              => fx(x, 0x29),
+        LdHf(x)             => fx(x, 0x30),
+        LdB(x)              => fx(x, 0x33),
+        Pitch(x)            => fx(x, 0x3A),
+        Store(x)            => fx(x, 0x55),
+        Load(x)             => fx(x, 0x65),
+        StoreRpl(x)         => fx(x, 0x75),
+        LoadRpl(x)          => fx(x, 0x85),
+        Unknown(opcode)     => opcode,
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::Instruction::*;
+
+        match *self {
+            Halt                => write!(f, "HALT"),
+            ScrollDown(n)       => write!(f, "SCD {}", n),
+            ScrollUp(n)         => write!(f, "SCU {}", n),
+            Cls                 => write!(f, "CLS"),
+            Ret                 => write!(f, "RET"),
+            ScrollRight         => write!(f, "SCR"),
+            ScrollLeft          => write!(f, "SCL"),
+            Exit                => write!(f, "EXIT"),
+            Lores               => write!(f, "LOW"),
+            Hires               => write!(f, "HIGH"),
+            HiresCls            => write!(f, "HCLS"),
+            Jp(nnn)             => write!(f, "JP 0x{:03X}", nnn),
+            Call(nnn)           => write!(f, "CALL 0x{:03X}", nnn),
+            SeByte(x, kk)       => write!(f, "SE V{:X}, 0x{:02X}", x, kk),
+            SneByte(x, kk)      => write!(f, "SNE V{:X}, 0x{:02X}", x, kk),
+            SeReg(x, y)         => write!(f, "SE V{:X}, V{:X}", x, y),
+            SaveRange(x, y)     => write!(f, "LD [I], V{:X}-V{:X}", x, y),
+            LoadRange(x, y)     => write!(f, "LD V{:X}-V{:X}, [I]", x, y),
+            LdByte(x, kk)       => write!(f, "LD V{:X}, 0x{:02X}", x, kk),
+            AddByte(x, kk)      => write!(f, "ADD V{:X}, 0x{:02X}", x, kk),
+            LdReg(x, y)         => write!(f, "LD V{:X}, V{:X}", x, y),
+            Or(x, y)            => write!(f, "OR V{:X}, V{:X}", x, y),
+            And(x, y)           => write!(f, "AND V{:X}, V{:X}", x, y),
+            Xor(x, y)           => write!(f, "XOR V{:X}, V{:X}", x, y),
+            AddReg(x, y)        => write!(f, "ADD V{:X}, V{:X}", x, y),
+            Sub(x, y)           => write!(f, "SUB V{:X}, V{:X}", x, y),
+            Shr(x, y)           => write!(f, "SHR V{:X}, V{:X}", x, y),
+            Subn(x, y)          => write!(f, "SUBN V{:X}, V{:X}", x, y),
+            Shl(x, y)           => write!(f, "SHL V{:X}, V{:X}", x, y),
+            SneReg(x, y)        => write!(f, "SNE V{:X}, V{:X}", x, y),
+            LdI(nnn)            => write!(f, "LD I, 0x{:03X}", nnn),
+            JpV0(nnn)           => write!(f, "JP V0, 0x{:03X}", nnn),
+            Rnd(x, kk)          => write!(f, "RND V{:X}, 0x{:02X}", x, kk),
+            Drw(x, y, n)        => write!(f, "DRW V{:X}, V{:X}, {}", x, y, n),
+            Skp(x)              => write!(f, "SKP V{:X}", x),
+            Sknp(x)             => write!(f, "SKNP V{:X}", x),
+            LdILong             => write!(f, "LD I, long"),
+            Plane(n)            => write!(f, "PLANE {}", n),
+            Audio               => write!(f, "AUDIO"),
+            LdVxDt(x)           => write!(f, "LD V{:X}, DT", x),
+            LdVxK(x)            => write!(f, "LD V{:X}, K", x),
+            LdDtVx(x)           => write!(f, "LD DT, V{:X}", x),
+            LdStVx(x)           => write!(f, "LD ST, V{:X}", x),
+            AddIVx(x)           => write!(f, "ADD I, V{:X}", x),
+            LdF(x)              => write!(f, "LD F, V{:X}", x),
+            LdHf(x)             => write!(f, "LD HF, V{:X}", x),
+            LdB(x)              => write!(f, "LD B, V{:X}", x),
+            Pitch(x)            => write!(f, "PITCH V{:X}", x),
+            Store(x)            => write!(f, "LD [I], V{:X}", x),
+            Load(x)             => write!(f, "LD V{:X}, [I]", x),
+            StoreRpl(x)         => write!(f, "LD R, V{:X}", x),
+            LoadRpl(x)          => write!(f, "LD V{:X}, R", x),
+            Unknown(opcode)     => write!(f, "DW 0x{:04X}", opcode),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_inverts_decode() {
+        for opcode in 0..=0xFFFF {
+            assert_eq!(encode(decode(opcode)), opcode, "{:04X} decodes to {:?}", opcode, decode(opcode));
+        }
+    }
+
+    #[test]
+    fn decodes_operands() {
+        assert_eq!(decode(0x00C3), Instruction::ScrollDown(3));
+        assert_eq!(decode(0x2ABC), Instruction::Call(0xABC));
+        assert_eq!(decode(0x5AB2), Instruction::SaveRange(0xA, 0xB));
+        assert_eq!(decode(0xD125), Instruction::Drw(1, 2, 5));
+        assert_eq!(decode(0xF201), Instruction::Plane(2));
+        assert_eq!(decode(0x8AB9), Instruction::Unknown(0x8AB9));
+    }
+
+    #[test]
+    fn displays_assembly() {
+        let cases = [
+            (0x0000, "HALT"),
+            (0x00E0, "CLS"),
+            (0x00EE, "RET"),
+            (0x00D4, "SCU 4"),
+            (0x1234, "JP 0x234"),
+            (0x3A0F, "SE VA, 0x0F"),
+            (0x5123, "LD V1-V2, [I]"),
+            (0x8AB4, "ADD VA, VB"),
+            (0x8AB6, "SHR VA, VB"),
+            (0xA050, "LD I, 0x050"),
+            (0xB300, "JP V0, 0x300"),
+            (0xC7FF, "RND V7, 0xFF"),
+            (0xD01F, "DRW V0, V1, 15"),
+            (0xE59E, "SKP V5"),
+            (0xF000, "LD I, long"),
+            (0xF30A, "LD V3, K"),
+            (0xFF65, "LD VF, [I]"),
+            (0xFFFF, "DW 0xFFFF"),
+        ];
+        for &(opcode, text) in cases.iter() {
+            assert_eq!(decode(opcode).to_string(), text);
+        }
+    }
+}
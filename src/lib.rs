@@ -1,19 +1,63 @@
 extern crate rand;
+extern crate toml;
+extern crate png;
+extern crate gif;
+#[cfg(feature = "gui")]
 extern crate piston;
+#[cfg(feature = "gui")]
 extern crate graphics;
+#[cfg(feature = "gui")]
 extern crate glutin_window;
+#[cfg(feature = "gui")]
 extern crate opengl_graphics;
+#[cfg(feature = "tui")]
+extern crate termion;
 
 pub mod cpu;
+pub mod machine;
+#[cfg(feature = "gui")]
 pub mod chip8;
+#[cfg(feature = "tui")]
+pub mod terminal;
 pub mod hardware;
 pub mod memory;
 pub mod drivers;
 pub mod debugger;
+pub mod quirks;
+pub mod options;
+pub mod error;
+pub mod instruction;
+pub mod savestate;
+pub mod rewind;
+pub mod movie;
+pub mod config;
+pub mod audio;
+pub mod palette;
+pub mod phosphor;
+pub mod screenshot;
+pub mod video;
+pub mod viewport;
 
 pub use self::cpu::*;
+pub use self::machine::*;
+#[cfg(feature = "gui")]
 pub use self::chip8::*;
+#[cfg(feature = "tui")]
+pub use self::terminal::*;
 pub use self::hardware::*;
 pub use self::memory::*;
 pub use self::drivers::*;
-pub use self::debugger::*;
\ No newline at end of file
+pub use self::debugger::*;
+pub use self::quirks::*;
+pub use self::options::*;
+pub use self::error::*;
+pub use self::instruction::*;
+pub use self::rewind::*;
+pub use self::movie::*;
+pub use self::config::*;
+pub use self::audio::*;
+pub use self::palette::*;
+pub use self::phosphor::*;
+pub use self::screenshot::*;
+pub use self::video::*;
+pub use self::viewport::*;
@@ -0,0 +1,457 @@
+use std::sync::{Arc, Mutex};
+use std::fs::File;
+use std::io::{BufWriter, Read, Write};
+use std::path::Path;
+use hardware::{AudioDriver, DisplayDriver, KeyboardDriver, RandomDriver};
+use memory::Memory;
+use debugger::{Debugger, DebugCommand};
+use cpu::Cpu;
+use quirks::Quirks;
+use error::Chip8Error;
+use savestate;
+use audio::SquareWave;
+use palette::Palette;
+use screenshot::{self, SCREENSHOT_SCALE};
+use movie::{Movie, Playback};
+use drivers::random_driver;
+use video::VideoRecorder;
+
+pub const FONT_SET: [u8; 80] =
+[
+    0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
+    0x20, 0x60, 0x20, 0x20, 0x70, // 1
+    0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
+    0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
+    0x90, 0x90, 0xF0, 0x10, 0x10, // 4
+    0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
+    0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
+    0xF0, 0x10, 0x20, 0x40, 0x40, // 7
+    0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
+    0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
+    0xF0, 0x90, 0xF0, 0x90, 0x90, // A
+    0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
+    0xF0, 0x80, 0x80, 0x80, 0xF0, // C
+    0xE0, 0x90, 0x90, 0x90, 0xE0, // D
+    0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
+    0xF0, 0x80, 0xF0, 0x80, 0x80, // F
+];
+
+pub const BIG_FONT_SET: [u8; 160] =
+[
+    0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, // 0
+    0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF, // 1
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // 2
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 3
+    0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 5
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 6
+    0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18, // 7
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 8
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 9
+    0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+    0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, // B
+    0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, // C
+    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0, // F
+];
+
+pub const BIG_FONT_ADDR: usize = 0x50;
+pub const OPCODE_SIZE: u16 = 2;
+pub const PROGRAM_START: usize = 0x200;
+pub const MEMORY_SIZE: usize = 0x10000;
+pub const CHIP8_WIDTH: usize = 64;
+pub const CHIP8_HEIGHT: usize = 32;
+pub const SCHIP_WIDTH: usize = 128;
+pub const SCHIP_HEIGHT: usize = 64;
+pub const VIP_HIRES_WIDTH: usize = 64;
+pub const VIP_HIRES_HEIGHT: usize = 64;
+pub const CLOCK_FREQ: u64 = 512;
+pub const TIMER_FREQ: u64 = 60;
+pub const MAX_INSTRUCTIONS_PER_FRAME: u32 = 10_000;
+
+/// The emulated machine without any frontend attached: CPU, memory, timers
+/// and input state. It can be stepped programmatically, which makes it usable
+/// from tests and tools that have no display.
+pub struct Machine<A, K>
+    where
+        A: AudioDriver + Sync + Send,
+        K: KeyboardDriver + Sync + Send,
+{
+    pub memory: Arc<Mutex<Memory>>,
+    pub cpu: Arc<Mutex<Cpu>>,
+    pub keyboard: K,
+    pub audio: A,
+    pub synth: SquareWave,
+    pub instructions_per_frame: u32,
+    pub frame: u64,
+    pub rom_name: String,
+    pub recording: Option<Movie>,
+    pub playback: Option<Playback>,
+    pub video: Option<VideoRecorder>,
+    /// Why the video stopped recording on its own, for the frontend to
+    /// report.
+    pub video_error: Option<Chip8Error>,
+    pub debugger: Debugger,
+}
+
+impl<A, K> Machine<A, K>
+    where
+        A: AudioDriver + Sync + Send,
+        K: KeyboardDriver + Sync + Send,
+{
+    pub fn new(audio: A, keyboard: K, quirks: Quirks) -> Self {
+        let memory = Arc::new(Mutex::new(Memory::new()));
+        let cpu = Arc::new(Mutex::new(Cpu::new(memory.clone(), quirks)));
+        let debugger = Debugger::new(cpu.clone(), memory.clone());
+        let instructions_per_frame = (CLOCK_FREQ / TIMER_FREQ) as u32;
+
+        Machine {
+            memory,
+            cpu,
+            keyboard,
+            audio,
+            synth: SquareWave::default(),
+            instructions_per_frame,
+            frame: 0,
+            rom_name: String::from("chip8"),
+            recording: None,
+            playback: None,
+            video: None,
+            video_error: None,
+            debugger,
+        }
+    }
+
+    pub fn load_program(&mut self, program: &[u8]) -> Result<(), Chip8Error> {
+        let max = MEMORY_SIZE - PROGRAM_START;
+        if program.len() > max {
+            return Err(Chip8Error::RomTooLarge { size: program.len(), max });
+        }
+        let mut memory = self.memory.lock().unwrap();
+        memory.ram[PROGRAM_START..PROGRAM_START + program.len()].copy_from_slice(program);
+        Ok(())
+    }
+
+    /// Executes a single CPU tick. A program halting the machine is reported
+    /// through the debugger's `stopped`.
+    pub fn tick(&mut self) -> Result<(), Chip8Error> {
+        let mut cpu = self.cpu.lock().unwrap();
+        let running = !cpu.halt;
+        cpu.tick(&mut self.keyboard, &self.debugger)?;
+        if running && cpu.halt {
+            self.debugger.halted(&cpu);
+        }
+        Ok(())
+    }
+
+    /// Executes `count` CPU ticks, stopping at the first fault or when the
+    /// debugger pauses.
+    pub fn run(&mut self, count: usize) -> Result<(), Chip8Error> {
+        for _ in 0..count {
+            if self.debugger.paused || self.debugger.check(&self.cpu.lock().unwrap()) {
+                break;
+            }
+            self.tick()?;
+        }
+        Ok(())
+    }
+
+    /// Runs a debugger command, returning what it printed. Commands that
+    /// run or change the machine outside whole frames are refused while a
+    /// movie is recorded or played, as the movie could not reproduce them.
+    pub fn debug(&mut self, command: DebugCommand) -> String {
+        if self.movie_active() && command.alters_execution() {
+            return Chip8Error::MovieActive.to_string();
+        }
+        self.debugger.execute(command, &mut self.keyboard)
+    }
+
+    /// Emulates one 60 Hz frame: runs `instructions_per_frame` instructions,
+    /// plays the tone if the sound timer is running, adds the frame to the
+    /// video being recorded and then counts the timers down once. A video that
+    /// fails to write is dropped, leaving the error in `video_error`, and the
+    /// frame carries on. A movie being played hands the keypad back once its
+    /// last event is delivered.
+    pub fn frame(&mut self) -> Result<(), Chip8Error> {
+        if let Some(ref mut playback) = self.playback {
+            for event in playback.due(self.frame) {
+                if event.pressed {
+                    self.keyboard.press(event.key);
+                } else {
+                    self.keyboard.release(event.key);
+                }
+            }
+        }
+        if self.playback.as_ref().is_some_and(|playback| playback.is_finished()) {
+            self.playback = None;
+        }
+        let instructions = self.instructions_per_frame as usize;
+        self.run(instructions)?;
+        self.keyboard.clear_edges();
+        let (sounding, pattern, pitch) = {
+            let cpu = self.cpu.lock().unwrap();
+            (cpu.sound_timer > 0, cpu.audio_pattern, cpu.pitch)
+        };
+        let samples = self.synth.frame(sounding, pattern.as_ref(), pitch);
+        self.audio.queue(samples);
+        let failed = match self.video {
+            Some(ref mut video) => video.capture(&self.memory.lock().unwrap().vram, samples).err(),
+            None => None,
+        };
+        if let Some(error) = failed {
+            self.video = None;
+            self.video_error = Some(error);
+        }
+        self.cpu.lock().unwrap().tick_timers();
+        self.frame += 1;
+        Ok(())
+    }
+
+    /// Hands the framebuffer to `display` and clears its dirty rows.
+    pub fn present<D: DisplayDriver>(&self, display: &mut D) {
+        let mut memory = self.memory.lock().unwrap();
+        display.refresh(&memory.vram, &memory.dirty_rows);
+        memory.clear_dirty();
+    }
+
+    /// Serializes the whole machine into a versioned save state.
+    pub fn save_state(&self) -> Vec<u8> {
+        let cpu = self.cpu.lock().unwrap();
+        let memory = self.memory.lock().unwrap();
+        savestate::snapshot(&cpu, &memory, self.frame)
+    }
+
+    /// Restores a save state. Refused while a movie is recorded or played,
+    /// as it moves the frame counter the movie is timed by.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), Chip8Error> {
+        if self.movie_active() {
+            return Err(Chip8Error::MovieActive);
+        }
+        let mut cpu = self.cpu.lock().unwrap();
+        let mut memory = self.memory.lock().unwrap();
+        self.frame = savestate::restore(&mut cpu, &mut memory, data)?;
+        Ok(())
+    }
+
+    pub fn save_state_file<P: AsRef<Path>>(&self, path: P) -> Result<(), Chip8Error> {
+        File::create(path)?.write_all(&self.save_state())?;
+        Ok(())
+    }
+
+    pub fn load_state_file<P: AsRef<Path>>(&mut self, path: P) -> Result<(), Chip8Error> {
+        let mut data = Vec::new();
+        File::open(path)?.read_to_end(&mut data)?;
+        self.load_state(&data)
+    }
+
+    /// File used for the numbered save state slot of the loaded ROM.
+    pub fn state_slot_path(&self, slot: u8) -> String {
+        format!("{}.state{}", self.rom_name, slot)
+    }
+
+    /// Writes the framebuffer to `<rom name>-<frame>.png`, scaled and in the
+    /// colours of `palette`, and to `<rom name>-<frame>.pbm`. Returns the
+    /// paths written.
+    pub fn save_screenshot(&self, palette: &Palette) -> Result<Vec<String>, Chip8Error> {
+        let memory = self.memory.lock().unwrap();
+        let base = format!("{}-{}", self.rom_name, self.frame);
+        let png = format!("{}.png", base);
+        let pbm = format!("{}.pbm", base);
+        let mut file = BufWriter::new(File::create(&png)?);
+        screenshot::write_png(&mut file, &memory.vram, palette, SCREENSHOT_SCALE)?;
+        file.flush()?;
+        let mut file = BufWriter::new(File::create(&pbm)?);
+        screenshot::write_pbm(&mut file, &memory.vram)?;
+        file.flush()?;
+        Ok(vec![png, pbm])
+    }
+
+    /// Starts recording video to `path` in the colours of `palette`; see
+    /// `VideoRecorder` for the formats. Any video already being recorded is
+    /// finished first.
+    pub fn start_video(&mut self, path: &str, palette: &Palette) -> Result<(), Chip8Error> {
+        self.stop_video()?;
+        self.video = Some(VideoRecorder::create(path, palette)?);
+        Ok(())
+    }
+
+    /// Finishes the video being recorded, returning where it went.
+    pub fn stop_video(&mut self) -> Result<Option<String>, Chip8Error> {
+        match self.video.take() {
+            Some(video) => {
+                let path = video.path.clone();
+                video.finish()?;
+                Ok(Some(path))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Path for a video started from the keyboard: `<rom name>-<frame>.gif`.
+    pub fn video_path(&self) -> String {
+        format!("{}-{}.gif", self.rom_name, self.frame)
+    }
+
+    /// Restarts the random sequence used by `Cxkk` from `seed`.
+    pub fn reseed(&mut self, seed: u64) {
+        self.cpu.lock().unwrap().reseed(seed);
+    }
+
+    pub fn set_random_driver(&mut self, rng: Box<dyn RandomDriver + Send>) {
+        self.cpu.lock().unwrap().set_random_driver(rng);
+    }
+
+    /// Starts recording keypad input into a movie, along with the current
+    /// random seed and source, the quirks and the instructions per frame.
+    /// Movies replay from power on, so this should be called before the first
+    /// frame runs.
+    pub fn start_recording(&mut self) {
+        let mut cpu = self.cpu.lock().unwrap();
+        let seed = cpu.seed;
+        cpu.reseed(seed);
+        let movie = Movie::new(seed, self.instructions_per_frame, cpu.rng.name(), cpu.quirks);
+        self.recording = Some(movie);
+    }
+
+    pub fn stop_recording(&mut self) -> Option<Movie> {
+        self.recording.take()
+    }
+
+    /// Replays a movie recorded with `start_recording`, switching to the
+    /// settings it was recorded with. Live keypad input is ignored until the
+    /// movie ends or is stopped.
+    pub fn play_movie(&mut self, movie: Movie) {
+        {
+            let mut cpu = self.cpu.lock().unwrap();
+            cpu.quirks = movie.quirks;
+            cpu.seed = movie.seed;
+            if let Some(rng) = random_driver(&movie.rng, movie.seed) {
+                cpu.set_random_driver(rng);
+            }
+        }
+        self.instructions_per_frame = movie.instructions_per_frame;
+        self.playback = Some(Playback::new(movie));
+    }
+
+    pub fn stop_movie(&mut self) {
+        self.playback = None;
+    }
+
+    /// Whether a movie is being recorded or played, which rules out anything
+    /// it cannot replay: loading states, rewinding, changing the speed and
+    /// stepping or editing the machine in the debugger.
+    pub fn movie_active(&self) -> bool {
+        self.recording.is_some() || self.playback.is_some()
+    }
+
+    pub fn press_key(&mut self, key: u8) {
+        self.input_key(key, true);
+    }
+
+    pub fn release_key(&mut self, key: u8) {
+        self.input_key(key, false);
+    }
+
+    fn input_key(&mut self, key: u8, pressed: bool) {
+        if self.playback.is_some() {
+            return;
+        }
+        if let Some(ref mut movie) = self.recording {
+            movie.record(self.frame, key, pressed);
+        }
+        if pressed {
+            self.keyboard.press(key);
+        } else {
+            self.keyboard.release(key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use drivers::{Keyboard, NullAudio};
+    use phosphor::Phosphor;
+
+    /// Keeps the dirty rows it was last given and whether the phosphor
+    /// found anything to redraw.
+    struct DirtyDisplay {
+        dirty: Vec<bool>,
+        phosphor: Phosphor,
+        redrawn: bool,
+    }
+
+    impl DisplayDriver for DirtyDisplay {
+        fn refresh(&mut self, vram: &[Vec<u8>], dirty: &[bool]) {
+            self.dirty = dirty.to_vec();
+            self.redrawn = self.phosphor.update(vram, dirty, &Palette::default());
+        }
+    }
+
+    fn machine(program: &[u8]) -> Machine<NullAudio, Keyboard> {
+        let mut machine = Machine::new(NullAudio, Keyboard::new(), Quirks::default());
+        machine.load_program(program).unwrap();
+        machine
+    }
+
+    #[test]
+    fn timers_count_once_per_frame_at_any_speed() {
+        for &ipf in [1, 8, 100, MAX_INSTRUCTIONS_PER_FRAME].iter() {
+            let mut machine = machine(&[0x12, 0x00]);
+            machine.instructions_per_frame = ipf;
+            machine.cpu.lock().unwrap().delay_timer = 60;
+            for _ in 0..30 {
+                machine.frame().unwrap();
+            }
+            assert_eq!(machine.cpu.lock().unwrap().delay_timer, 30, "{} instructions per frame", ipf);
+        }
+    }
+
+    #[test]
+    fn sound_timer_runs_while_waiting_for_a_key() {
+        let mut machine = machine(&[0xF0, 0x0A]);
+        machine.cpu.lock().unwrap().sound_timer = 60;
+        for _ in 0..30 {
+            machine.frame().unwrap();
+        }
+        let cpu = machine.cpu.lock().unwrap();
+        assert!(cpu.keypad_waiting);
+        assert_eq!(cpu.sound_timer, 30);
+    }
+
+    #[test]
+    fn reports_the_program_halting() {
+        let programs = [([0x00, 0xFD], "halted at 0x200: EXIT"), ([0x00, 0x00], "halted at 0x200: HALT")];
+        for &(program, reason) in programs.iter() {
+            let mut machine = machine(&program);
+            machine.frame().unwrap();
+            assert!(machine.cpu.lock().unwrap().halt);
+            assert_eq!(machine.debugger.stopped.take(), Some(reason.to_string()));
+            machine.frame().unwrap();
+            assert_eq!(machine.debugger.stopped, None);
+        }
+    }
+
+    #[test]
+    fn presents_only_the_rows_drawn_on() {
+        // Draws the font's "0" at (5, 5), then spins.
+        let mut machine = machine(&[0xA0, 0x00, 0x60, 0x05, 0xD0, 0x05, 0x12, 0x06]);
+        machine.instructions_per_frame = 3;
+        let mut display = DirtyDisplay { dirty: Vec::new(), phosphor: Phosphor::default(), redrawn: false };
+        machine.present(&mut display);
+        assert!(display.dirty.iter().all(|&dirty| dirty));
+        assert!(display.redrawn);
+
+        machine.frame().unwrap();
+        machine.present(&mut display);
+        let dirty: Vec<usize> = (0..32).filter(|&row| display.dirty[row]).collect();
+        assert_eq!(dirty, vec![5, 6, 7, 8, 9]);
+        assert!(display.redrawn);
+
+        machine.frame().unwrap();
+        machine.present(&mut display);
+        assert_eq!(display.dirty, vec![false; 32]);
+        assert!(!display.redrawn);
+    }
+}
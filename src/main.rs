@@ -1,30 +1,57 @@
 extern crate chip8;
-use std::io::Read;
-use std::path::Path;
 use std::env;
 use std::process;
-use std::fs::File;
-use chip8::Chip8;
-use chip8::drivers::{Keyboard, Audio};
+use chip8::{Chip8, Options, Phosphor, USAGE};
+use chip8::drivers::Keyboard;
 
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
+    let args: Vec<String> = env::args().skip(1).collect();
 
-    if args.get(1).is_none() {
-        eprintln!("{}", "Usage: chip8 /path/to/program.rom");
-        process::exit(1);
-    }
-
-    let filepath = &args[1];
-    let filepath = Path::new(filepath);
+    let options = match Options::parse(&args) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{}", message);
+            eprintln!("{}", USAGE);
+            process::exit(1);
+        }
+    };
 
-    let mut f = File::open(filepath).expect("file not found");
-    let mut rom = Vec::new();
+    let audio = match options.audio_driver() {
+        Ok(audio) => audio,
+        Err(message) => {
+            eprintln!("{}", message);
+            process::exit(1);
+        }
+    };
 
-    f.read_to_end(&mut rom).expect("failed to read .rom file");
-    let mut vm = Chip8::new(Audio {}, Keyboard::new());
+    let mut vm = Chip8::new(audio, Keyboard::new(), options.quirks);
+    let config = options.setup(&mut vm.machine).and_then(|()| options.config(&vm.machine.rom_name));
+    let config = config.and_then(|config| {
+        options.start_video(&mut vm.machine, &config.palette.unwrap_or_default())?;
+        Ok(config)
+    });
+    match config {
+        Ok(config) => {
+            vm.bindings = config.bindings;
+            vm.display.palette = config.palette.unwrap_or_default();
+            vm.display.phosphor = Phosphor::new(config.phosphor.unwrap_or_default());
+            vm.display.scaling = options.scaling;
+        }
+        Err(message) => {
+            eprintln!("{}", message);
+            process::exit(1);
+        }
+    }
 
-    vm.load_program(&rom);
+    if let Some(scale) = options.scale {
+        vm.set_scale(scale);
+    }
+    if options.fullscreen {
+        vm.set_fullscreen(true);
+    }
     vm.boot();
-}
\ No newline at end of file
+
+    options.save_video(&mut vm.machine);
+    options.save_movie(&mut vm.machine);
+}
@@ -1,23 +1,114 @@
-use chip8::{CHIP8_WIDTH, CHIP8_HEIGHT, FONT_SET};
+use error::Chip8Error;
+use machine::{CHIP8_WIDTH, CHIP8_HEIGHT, FONT_SET, BIG_FONT_SET, BIG_FONT_ADDR, MEMORY_SIZE};
 
+/// Each `vram` pixel holds a bitmask of the bitplanes it is lit on: bit 0 is
+/// the first plane and bit 1 the second (XO-CHIP).
+///
+/// `dirty_rows` flags the rows of `vram` changed since the frontend last took
+/// the framebuffer.
 pub struct Memory {
-    pub ram: [u8; 4096],
+    pub ram: Vec<u8>,
     pub stack: [u16; 16],
-    pub vram: [[u8; CHIP8_WIDTH]; CHIP8_HEIGHT],
-    pub vram_changed: bool
+    pub vram: Vec<Vec<u8>>,
+    pub dirty_rows: Vec<bool>,
 }
 
 impl Memory {
     pub fn new() -> Self {
         let stack = [0; 16];
-        let vram = [[0; CHIP8_WIDTH]; CHIP8_HEIGHT];
-        let mut ram = [0; 4096];
-        let vram_changed = true;
+        let vram = vec![vec![0; CHIP8_WIDTH]; CHIP8_HEIGHT];
+        let mut ram = vec![0; MEMORY_SIZE];
+        let dirty_rows = vec![true; CHIP8_HEIGHT];
 
-        for i in 0..FONT_SET.len() {
-            ram[i] = FONT_SET[i];
+        ram[..FONT_SET.len()].copy_from_slice(&FONT_SET);
+        ram[BIG_FONT_ADDR..BIG_FONT_ADDR + BIG_FONT_SET.len()].copy_from_slice(&BIG_FONT_SET);
+
+        Memory { ram, stack, vram, dirty_rows }
+    }
+
+    pub fn read(&self, addr: usize) -> Result<u8, Chip8Error> {
+        self.ram.get(addr).cloned().ok_or(Chip8Error::MemoryOutOfBounds { addr })
+    }
+
+    pub fn write(&mut self, addr: usize, value: u8) -> Result<(), Chip8Error> {
+        let byte = self.ram.get_mut(addr).ok_or(Chip8Error::MemoryOutOfBounds { addr })?;
+        *byte = value;
+        Ok(())
+    }
+
+    /// Borrows `len` bytes of ram starting at `addr`.
+    pub fn slice(&self, addr: usize, len: usize) -> Result<&[u8], Chip8Error> {
+        self.ram.get(addr..addr + len).ok_or(Chip8Error::MemoryOutOfBounds { addr: addr + len - 1 })
+    }
+
+    pub fn slice_mut(&mut self, addr: usize, len: usize) -> Result<&mut [u8], Chip8Error> {
+        self.ram.get_mut(addr..addr + len).ok_or(Chip8Error::MemoryOutOfBounds { addr: addr + len - 1 })
+    }
+
+    pub fn width(&self) -> usize {
+        self.vram[0].len()
+    }
+
+    pub fn height(&self) -> usize {
+        self.vram.len()
+    }
+
+    pub fn mark_dirty(&mut self, row: usize) {
+        self.dirty_rows[row] = true;
+    }
+
+    /// Flags every row, for changes to the whole framebuffer.
+    pub fn mark_all_dirty(&mut self) {
+        self.dirty_rows = vec![true; self.height()];
+    }
+
+    pub fn clear_dirty(&mut self) {
+        for dirty in self.dirty_rows.iter_mut() {
+            *dirty = false;
         }
+    }
 
-        Memory { ram, stack, vram, vram_changed }
+    /// Switches the framebuffer to a new resolution, clearing it.
+    pub fn resize_vram(&mut self, width: usize, height: usize) {
+        self.vram = vec![vec![0; width]; height];
+        self.mark_all_dirty();
     }
-}
\ No newline at end of file
+
+    /// Clears the bitplanes selected in `planes`.
+    pub fn clear_vram(&mut self, planes: u8) {
+        for row in self.vram.iter_mut() {
+            for pixel in row.iter_mut() {
+                *pixel &= !planes;
+            }
+        }
+        self.mark_all_dirty();
+    }
+
+    /// Moves the selected bitplanes by `dx` columns and `dy` rows, filling the
+    /// uncovered area with unlit pixels.
+    pub fn scroll(&mut self, planes: u8, dx: isize, dy: isize) {
+        let width = self.width() as isize;
+        let height = self.height() as isize;
+        let source = self.vram.clone();
+
+        for y in 0..height {
+            for x in 0..width {
+                let (src_x, src_y) = (x - dx, y - dy);
+                let moved = if src_x >= 0 && src_x < width && src_y >= 0 && src_y < height {
+                    source[src_y as usize][src_x as usize] & planes
+                } else {
+                    0
+                };
+                let pixel = &mut self.vram[y as usize][x as usize];
+                *pixel = (*pixel & !planes) | moved;
+            }
+        }
+        self.mark_all_dirty();
+    }
+}
+
+impl Default for Memory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
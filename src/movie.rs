@@ -0,0 +1,291 @@
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
+use error::Chip8Error;
+use drivers::RANDOM_DRIVERS;
+use machine::MAX_INSTRUCTIONS_PER_FRAME;
+use quirks::Quirks;
+
+pub const MOVIE_MAGIC: &str = "CHIP8-MOVIE";
+pub const MOVIE_VERSION: u32 = 2;
+
+/// A keypad press or release, delivered before the given frame runs.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct InputEvent {
+    pub frame: u64,
+    pub key: u8,
+    pub pressed: bool,
+}
+
+/// A recorded session: everything besides the ROM needed to replay it
+/// bit-for-bit from power on. The quirks are listed by the names of the ones
+/// turned on.
+///
+/// Movies are plain text so they can be attached to bug reports and read:
+///
+/// ```text
+/// CHIP8-MOVIE 2
+/// seed 1234
+/// ipf 8
+/// rng xorshift
+/// quirks shift_uses_vy load_store_increments_i logic_resets_vf
+/// 120 press 5
+/// 128 release 5
+/// ```
+///
+/// Events are sorted by frame.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Movie {
+    pub seed: u64,
+    pub instructions_per_frame: u32,
+    pub rng: String,
+    pub quirks: Quirks,
+    pub events: Vec<InputEvent>,
+}
+
+impl Movie {
+    pub fn new(seed: u64, instructions_per_frame: u32, rng: &str, quirks: Quirks) -> Self {
+        Movie {
+            seed,
+            instructions_per_frame,
+            rng: rng.to_string(),
+            quirks,
+            events: Vec::new(),
+        }
+    }
+
+    pub fn record(&mut self, frame: u64, key: u8, pressed: bool) {
+        self.events.push(InputEvent { frame, key, pressed });
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = format!("{} {}\nseed {}\nipf {}\n", MOVIE_MAGIC, MOVIE_VERSION, self.seed, self.instructions_per_frame);
+        text.push_str(&format!("rng {}\n", self.rng));
+        let mut quirks = vec!["quirks"];
+        quirks.extend(self.quirks.enabled());
+        text.push_str(&format!("{}\n", quirks.join(" ")));
+        for event in self.events.iter() {
+            let action = if event.pressed { "press" } else { "release" };
+            text.push_str(&format!("{} {} {:X}\n", event.frame, action, event.key));
+        }
+        text
+    }
+
+    pub fn parse(text: &str) -> Result<Self, Chip8Error> {
+        let mut lines = text.lines().filter(|line| !line.trim().is_empty());
+
+        let header = lines.next().unwrap_or("");
+        if header.trim() != format!("{} {}", MOVIE_MAGIC, MOVIE_VERSION) {
+            return Err(invalid(&format!("unsupported header '{}'", header)));
+        }
+
+        let seed = field(lines.next(), "seed")?;
+        let instructions_per_frame = field(lines.next(), "ipf")?;
+        if instructions_per_frame == 0 || instructions_per_frame > MAX_INSTRUCTIONS_PER_FRAME {
+            return Err(invalid(&format!("ipf {} out of range", instructions_per_frame)));
+        }
+        let rng: String = field(lines.next(), "rng")?;
+        if !RANDOM_DRIVERS.contains(&rng.as_str()) {
+            return Err(invalid(&format!("unknown random source '{}'", rng)));
+        }
+        let line = lines.next().unwrap_or("");
+        let quirks: Vec<&str> = line.split_whitespace().collect();
+        let quirks = match quirks.split_first() {
+            Some((&"quirks", names)) => Quirks::from_enabled(names),
+            _ => None,
+        };
+        let quirks = quirks.ok_or_else(|| invalid(&format!("expected 'quirks <names>', found '{}'", line)))?;
+        let mut movie = Movie::new(seed, instructions_per_frame, &rng, quirks);
+
+        for line in lines {
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            let event = match parts.as_slice() {
+                [frame, action, key] => {
+                    let frame = frame.parse().map_err(|_| invalid(line))?;
+                    let key = u8::from_str_radix(key, 16).ok().filter(|&key| key < 16).ok_or_else(|| invalid(line))?;
+                    let pressed = match *action {
+                        "press" => true,
+                        "release" => false,
+                        _ => return Err(invalid(line)),
+                    };
+                    InputEvent { frame, key, pressed }
+                }
+                _ => return Err(invalid(line)),
+            };
+            if movie.events.last().is_some_and(|last| last.frame > event.frame) {
+                return Err(invalid(&format!("'{}' is out of order", line)));
+            }
+            movie.events.push(event);
+        }
+
+        Ok(movie)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Chip8Error> {
+        File::create(path)?.write_all(self.to_text().as_bytes())?;
+        Ok(())
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Chip8Error> {
+        let mut text = String::new();
+        File::open(path)?.read_to_string(&mut text)?;
+        Self::parse(&text)
+    }
+}
+
+/// A movie being replayed, with the position of the next event to deliver.
+pub struct Playback {
+    pub movie: Movie,
+    pub position: usize,
+}
+
+impl Playback {
+    pub fn new(movie: Movie) -> Self {
+        Playback { movie, position: 0 }
+    }
+
+    /// Events due before `frame` runs that have not been delivered yet.
+    pub fn due(&mut self, frame: u64) -> &[InputEvent] {
+        let start = self.position;
+        while self.position < self.movie.events.len() && self.movie.events[self.position].frame <= frame {
+            self.position += 1;
+        }
+        &self.movie.events[start..self.position]
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.position >= self.movie.events.len()
+    }
+}
+
+fn field<T: ::std::str::FromStr>(line: Option<&str>, name: &str) -> Result<T, Chip8Error> {
+    let line = line.unwrap_or("");
+    let mut parts = line.split_whitespace();
+    match (parts.next(), parts.next().and_then(|value| value.parse().ok())) {
+        (Some(key), Some(value)) if key == name => Ok(value),
+        _ => Err(invalid(&format!("expected '{} <value>', found '{}'", name, line))),
+    }
+}
+
+fn invalid(reason: &str) -> Chip8Error {
+    Chip8Error::InvalidMovie(reason.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use machine::Machine;
+    use drivers::{Keyboard, NullAudio};
+
+    const HEADER: &str = "CHIP8-MOVIE 2\nseed 1234\nipf 8\nrng xorshift\nquirks shift_uses_vy wrap_sprites\n";
+
+    fn movie() -> Movie {
+        let mut movie = Movie::new(1234, 8, "xorshift", Quirks::xochip());
+        movie.record(0, 0x5, true);
+        movie.record(3, 0x5, false);
+        movie.record(3, 0xA, true);
+        movie.record(90, 0xA, false);
+        movie
+    }
+
+    #[test]
+    fn round_trips_through_text() {
+        for quirks in [Quirks::vip(), Quirks::schip(), Quirks::xochip()].iter() {
+            let mut movie = movie();
+            movie.quirks = *quirks;
+            assert_eq!(Movie::parse(&movie.to_text()), Ok(movie));
+        }
+    }
+
+    #[test]
+    fn parses_the_documented_format() {
+        let movie = Movie::parse(&format!("{}\n120 press 5\n128 release f\n", HEADER)).unwrap();
+        assert_eq!(movie.seed, 1234);
+        assert_eq!(movie.instructions_per_frame, 8);
+        assert_eq!(movie.rng, "xorshift");
+        assert_eq!(movie.quirks.enabled(), vec!["shift_uses_vy", "wrap_sprites"]);
+        assert_eq!(movie.events, vec![
+            InputEvent { frame: 120, key: 0x5, pressed: true },
+            InputEvent { frame: 128, key: 0xF, pressed: false },
+        ]);
+        assert!(Movie::parse(&HEADER.replace("quirks shift_uses_vy wrap_sprites", "quirks")).is_ok());
+    }
+
+    #[test]
+    fn rejects_bad_headers() {
+        let headers = [
+            String::new(),
+            HEADER.replace("MOVIE 2", "MOVIE 1"),
+            HEADER.replace("seed 1234", "seed x"),
+            HEADER.replace("ipf 8", "ipf 0"),
+            HEADER.replace("ipf 8", "ipf 4000000000"),
+            HEADER.replace("rng xorshift", "rng dice"),
+            HEADER.replace("wrap_sprites", "wrap"),
+            HEADER.replace("quirks ", "quirk "),
+            HEADER.replace("ipf 8\nrng xorshift\n", "ipf 8\n"),
+        ];
+        for header in headers.iter() {
+            match Movie::parse(header) {
+                Err(Chip8Error::InvalidMovie(_)) => {}
+                result => panic!("expected '{}' to be rejected, got {:?}", header, result),
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_bad_events() {
+        let events = [
+            "120 press",
+            "120 push 5",
+            "120 press 10",
+            "-1 press 5",
+            "later press 5",
+            "120 press 5 now",
+            "120 press 5\n119 release 5",
+        ];
+        for events in events.iter() {
+            assert!(Movie::parse(&format!("{}{}\n", HEADER, events)).is_err(), "{}", events);
+        }
+    }
+
+    #[test]
+    fn plays_events_as_their_frames_come() {
+        let mut playback = Playback::new(movie());
+        assert_eq!(playback.due(0).len(), 1);
+        assert!(playback.due(0).is_empty());
+        assert!(playback.due(2).is_empty());
+        assert_eq!(playback.due(3), &movie().events[1..3]);
+        assert!(!playback.is_finished());
+        assert_eq!(playback.due(200), &movie().events[3..]);
+        assert!(playback.is_finished());
+    }
+
+    #[test]
+    fn replays_a_recorded_session() {
+        // Adds random bytes to V1 while key 5 is held.
+        let program = [0x65, 0x05, 0xE5, 0x9E, 0x12, 0x00, 0xC0, 0xFF, 0x81, 0x04, 0x12, 0x00];
+        let mut recorder = Machine::new(NullAudio, Keyboard::new(), Quirks::xochip());
+        recorder.reseed(77);
+        recorder.load_program(&program).unwrap();
+        recorder.start_recording();
+        for frame in 0..60 {
+            match frame {
+                10 | 40 => recorder.press_key(5),
+                20 | 50 => recorder.release_key(5),
+                _ => {}
+            }
+            recorder.frame().unwrap();
+        }
+        assert!(recorder.load_state(&recorder.save_state()).is_err());
+        let movie = Movie::parse(&recorder.stop_recording().unwrap().to_text()).unwrap();
+
+        let mut player = Machine::new(NullAudio, Keyboard::new(), Quirks::vip());
+        player.load_program(&program).unwrap();
+        player.play_movie(movie);
+        for _ in 0..60 {
+            player.frame().unwrap();
+        }
+        assert!(player.playback.is_none());
+        assert_eq!(player.save_state(), recorder.save_state());
+    }
+}
@@ -0,0 +1,284 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use machine::{Machine, CLOCK_FREQ, MAX_INSTRUCTIONS_PER_FRAME, TIMER_FREQ};
+use quirks::{Quirks, QUIRK_PRESETS};
+use hardware::{AudioDriver, KeyboardDriver};
+use drivers::{random_driver, NullAudio, WavWriter, RANDOM_DRIVERS};
+use audio::{AUDIO_SAMPLE_RATE, DEFAULT_TONE_FREQ, DEFAULT_VOLUME};
+use config::{Config, DEFAULT_CONFIG_PATH};
+use movie::Movie;
+use palette::Palette;
+use phosphor::PhosphorMode;
+use viewport::{ScaleMode, MAX_SCALE};
+
+pub const USAGE: &str = "Usage: chip8 [options] /path/to/program.rom
+
+Options:
+    --quirks vip|chip48|schip|xochip    interpreter behaviour to emulate
+    --ipf N                             instructions executed per 60 Hz frame
+    --seed N                            seed for the random number source
+    --rng xorshift                      random number source used by CXKK
+    --tone HZ                           pitch of the beeper (default: 440)
+    --volume V                          beeper volume from 0 to 1 (default: 0.25)
+    --wav FILE                          write the sound to a WAV file
+    --palette NAME                      default, green, amber, vip or contrast
+    --phosphor MODE                     off, decay[:0-1] or or[:FRAMES] to reduce flicker
+    --scale N                           initial window size in multiples of 64x32 (default: 10)
+    --scaling integer|fit               scale the screen by whole multiples or to fill the window
+    --fullscreen                        start fullscreen
+    --config FILE                       key bindings (default: chip8.toml if present)
+    --record FILE.gif|DIR               record video to a GIF, or to PNG frames and a WAV in DIR
+    --record-movie FILE                 record keypad input to a movie file
+    --play-movie FILE                   replay a movie file";
+
+/// Settings collected from the command line.
+pub struct Options {
+    pub rom: String,
+    pub quirks: Quirks,
+    pub instructions_per_frame: u32,
+    pub seed: Option<u64>,
+    pub rng: String,
+    pub config: Option<String>,
+    pub tone: f64,
+    pub volume: f64,
+    pub wav: Option<String>,
+    pub palette: Option<Palette>,
+    pub phosphor: Option<PhosphorMode>,
+    pub scale: Option<usize>,
+    pub scaling: ScaleMode,
+    pub fullscreen: bool,
+    pub record: Option<String>,
+    pub record_movie: Option<String>,
+    pub play_movie: Option<String>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            rom: String::new(),
+            quirks: Quirks::default(),
+            instructions_per_frame: (CLOCK_FREQ / TIMER_FREQ) as u32,
+            seed: None,
+            rng: String::from("xorshift"),
+            config: None,
+            tone: DEFAULT_TONE_FREQ,
+            volume: DEFAULT_VOLUME,
+            wav: None,
+            palette: None,
+            phosphor: None,
+            scale: None,
+            scaling: ScaleMode::default(),
+            fullscreen: false,
+            record: None,
+            record_movie: None,
+            play_movie: None,
+        }
+    }
+}
+
+impl Options {
+    /// Parses the arguments following the program name.
+    pub fn parse(args: &[String]) -> Result<Self, String> {
+        let mut options = Options::default();
+        let mut rom = None;
+        let mut args = args.iter();
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--quirks" => {
+                    let name = args.next().ok_or("--quirks expects a preset name")?;
+                    options.quirks = Quirks::from_name(name).ok_or_else(|| {
+                        format!("unknown quirks preset '{}', expected one of: {}", name, QUIRK_PRESETS.join(", "))
+                    })?;
+                }
+                "--ipf" => {
+                    let value = args.next().ok_or("--ipf expects a number")?;
+                    options.instructions_per_frame = match value.parse() {
+                        Ok(ipf) if ipf > 0 && ipf <= MAX_INSTRUCTIONS_PER_FRAME => ipf,
+                        _ => return Err(format!(
+                            "invalid instructions per frame '{}', expected 1 to {}", value, MAX_INSTRUCTIONS_PER_FRAME
+                        )),
+                    };
+                }
+                "--seed" => {
+                    let value = args.next().ok_or("--seed expects a number")?;
+                    options.seed = Some(value.parse().map_err(|_| format!("invalid seed '{}'", value))?);
+                }
+                "--rng" => {
+                    let name = args.next().ok_or("--rng expects a source name")?;
+                    if !RANDOM_DRIVERS.contains(&name.as_str()) {
+                        return Err(format!("unknown random source '{}', expected one of: {}", name, RANDOM_DRIVERS.join(", ")));
+                    }
+                    options.rng = name.clone();
+                }
+                "--config" => {
+                    options.config = Some(args.next().ok_or("--config expects a file")?.clone());
+                }
+                "--tone" => {
+                    let value = args.next().ok_or("--tone expects a frequency")?;
+                    options.tone = match value.parse() {
+                        Ok(tone) if tone > 0.0 => tone,
+                        _ => return Err(format!("invalid tone frequency '{}'", value)),
+                    };
+                }
+                "--volume" => {
+                    let value = args.next().ok_or("--volume expects a number")?;
+                    options.volume = match value.parse() {
+                        Ok(volume) if (0.0..=1.0).contains(&volume) => volume,
+                        _ => return Err(format!("invalid volume '{}', expected 0 to 1", value)),
+                    };
+                }
+                "--wav" => {
+                    options.wav = Some(args.next().ok_or("--wav expects a file")?.clone());
+                }
+                "--palette" => {
+                    let name = args.next().ok_or("--palette expects a name")?;
+                    options.palette = Some(Palette::from_name(name).ok_or_else(|| {
+                        format!("unknown palette '{}', expected one of: {}", name, Palette::names().join(", "))
+                    })?);
+                }
+                "--phosphor" => {
+                    let name = args.next().ok_or("--phosphor expects a mode")?;
+                    options.phosphor = Some(PhosphorMode::from_name(name).ok_or_else(|| {
+                        format!("invalid phosphor mode '{}', expected off, decay[:0-1] or or[:FRAMES]", name)
+                    })?);
+                }
+                "--scale" => {
+                    let value = args.next().ok_or("--scale expects a number")?;
+                    options.scale = match value.parse() {
+                        Ok(scale) if scale > 0 && scale <= MAX_SCALE => Some(scale),
+                        _ => return Err(format!("invalid scale '{}', expected 1 to {}", value, MAX_SCALE)),
+                    };
+                }
+                "--scaling" => {
+                    let name = args.next().ok_or("--scaling expects a mode")?;
+                    options.scaling = ScaleMode::from_name(name)
+                        .ok_or_else(|| format!("unknown scaling '{}', expected integer or fit", name))?;
+                }
+                "--fullscreen" => options.fullscreen = true,
+                "--record" => {
+                    options.record = Some(args.next().ok_or("--record expects a file or directory")?.clone());
+                }
+                "--record-movie" => {
+                    options.record_movie = Some(args.next().ok_or("--record-movie expects a file")?.clone());
+                }
+                "--play-movie" => {
+                    options.play_movie = Some(args.next().ok_or("--play-movie expects a file")?.clone());
+                }
+                _ if arg.starts_with("--") => return Err(format!("unknown option '{}'", arg)),
+                _ => rom = Some(arg.clone()),
+            }
+        }
+
+        if options.record_movie.is_some() && options.play_movie.is_some() {
+            return Err(String::from("--record-movie and --play-movie cannot be combined"));
+        }
+
+        options.rom = rom.ok_or("no ROM given")?;
+        Ok(options)
+    }
+
+    /// Loads the ROM into `machine` and applies the machine settings, including
+    /// starting movie playback or recording.
+    pub fn setup<A, K>(&self, machine: &mut Machine<A, K>) -> Result<(), String>
+        where
+            A: AudioDriver + Sync + Send,
+            K: KeyboardDriver + Sync + Send,
+    {
+        let path = Path::new(&self.rom);
+        let mut rom = Vec::new();
+        File::open(path)
+            .and_then(|mut file| file.read_to_end(&mut rom))
+            .map_err(|error| format!("{}: {}", self.rom, error))?;
+        if let Some(name) = path.file_stem() {
+            machine.rom_name = name.to_string_lossy().into_owned();
+        }
+
+        machine.instructions_per_frame = self.instructions_per_frame;
+        machine.synth.frequency = self.tone;
+        machine.synth.volume = self.volume;
+        if let Some(rng) = random_driver(&self.rng, 0) {
+            machine.set_random_driver(rng);
+        }
+        if let Some(seed) = self.seed {
+            machine.reseed(seed);
+        }
+        machine.load_program(&rom).map_err(|error| error.to_string())?;
+
+        if let Some(ref path) = self.play_movie {
+            let movie = Movie::load(path).map_err(|error| format!("{}: {}", path, error))?;
+            machine.play_movie(movie);
+        }
+        if self.record_movie.is_some() {
+            machine.start_recording();
+        }
+        Ok(())
+    }
+
+    /// The WAV writer when `--wav` was given, otherwise silence.
+    pub fn audio_driver(&self) -> Result<Box<dyn AudioDriver + Send + Sync>, String> {
+        match self.wav {
+            Some(ref path) => match WavWriter::create(path, AUDIO_SAMPLE_RATE) {
+                Ok(writer) => Ok(Box::new(writer)),
+                Err(error) => Err(format!("{}: {}", path, error)),
+            },
+            None => Ok(Box::new(NullAudio)),
+        }
+    }
+
+    /// Reads `--config`, or `chip8.toml` when it exists, for the given ROM.
+    /// A `--palette` or `--phosphor` given on the command line wins over the
+    /// file's.
+    pub fn config(&self, rom_name: &str) -> Result<Config, String> {
+        let config = match self.config {
+            Some(ref path) => Config::load(path, rom_name),
+            None if Path::new(DEFAULT_CONFIG_PATH).exists() => Config::load(DEFAULT_CONFIG_PATH, rom_name),
+            None => Ok(Config::default()),
+        };
+        let mut config = config.map_err(|error| error.to_string())?;
+        config.palette = self.palette.or(config.palette);
+        config.phosphor = self.phosphor.or(config.phosphor);
+        Ok(config)
+    }
+
+    /// Starts the video asked for with `--record`, drawn in `palette`.
+    pub fn start_video<A, K>(&self, machine: &mut Machine<A, K>, palette: &Palette) -> Result<(), String>
+        where
+            A: AudioDriver + Sync + Send,
+            K: KeyboardDriver + Sync + Send,
+    {
+        match self.record {
+            Some(ref path) => machine.start_video(path, palette).map_err(|error| format!("{}: {}", path, error)),
+            None => Ok(()),
+        }
+    }
+
+    /// Finishes the video still being recorded on exit, whether it came from
+    /// `--record` or the record hotkey.
+    pub fn save_video<A, K>(&self, machine: &mut Machine<A, K>)
+        where
+            A: AudioDriver + Sync + Send,
+            K: KeyboardDriver + Sync + Send,
+    {
+        match machine.stop_video() {
+            Ok(Some(path)) => println!("Saved video to {}", path),
+            Ok(None) => {}
+            Err(error) => eprintln!("{}", error),
+        }
+    }
+
+    /// Writes the movie recorded since `setup`, if `--record-movie` was given.
+    pub fn save_movie<A, K>(&self, machine: &mut Machine<A, K>)
+        where
+            A: AudioDriver + Sync + Send,
+            K: KeyboardDriver + Sync + Send,
+    {
+        if let (Some(ref path), Some(movie)) = (self.record_movie.as_ref(), machine.stop_recording()) {
+            match movie.save(path) {
+                Ok(()) => println!("Saved movie to {}", path),
+                Err(error) => eprintln!("{}: {}", path, error),
+            }
+        }
+    }
+}
@@ -0,0 +1,83 @@
+/// Colours for each bitplane mask of a pixel: background, first plane,
+/// second plane and both planes. Single-plane programs only use the first
+/// two.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Palette {
+    pub name: &'static str,
+    pub colors: [[u8; 4]; 4],
+}
+
+pub const PALETTES: [Palette; 5] = [
+    Palette {
+        name: "default",
+        colors: [
+            [0x4C, 0x4C, 0x4C, 0xFF],
+            [0xFF, 0x21, 0x6E, 0xFF],
+            [0x21, 0x99, 0xFF, 0xFF],
+            [0xFF, 0xFF, 0xFF, 0xFF],
+        ],
+    },
+    Palette {
+        name: "green",
+        colors: [
+            [0x0A, 0x14, 0x0A, 0xFF],
+            [0x33, 0xFF, 0x66, 0xFF],
+            [0x1A, 0x80, 0x33, 0xFF],
+            [0xB3, 0xFF, 0xC6, 0xFF],
+        ],
+    },
+    Palette {
+        name: "amber",
+        colors: [
+            [0x14, 0x0C, 0x00, 0xFF],
+            [0xFF, 0xB0, 0x00, 0xFF],
+            [0x99, 0x5C, 0x00, 0xFF],
+            [0xFF, 0xE0, 0x99, 0xFF],
+        ],
+    },
+    Palette {
+        name: "vip",
+        colors: [
+            [0x00, 0x00, 0x00, 0xFF],
+            [0xFF, 0xFF, 0xFF, 0xFF],
+            [0xAA, 0xAA, 0xAA, 0xFF],
+            [0x55, 0x55, 0x55, 0xFF],
+        ],
+    },
+    Palette {
+        name: "contrast",
+        colors: [
+            [0x00, 0x00, 0x00, 0xFF],
+            [0xFF, 0xFF, 0x00, 0xFF],
+            [0x00, 0xFF, 0xFF, 0xFF],
+            [0xFF, 0xFF, 0xFF, 0xFF],
+        ],
+    },
+];
+
+impl Palette {
+    pub fn from_name(name: &str) -> Option<Self> {
+        PALETTES.iter().find(|palette| palette.name.eq_ignore_ascii_case(name)).cloned()
+    }
+
+    pub fn names() -> Vec<&'static str> {
+        PALETTES.iter().map(|palette| palette.name).collect()
+    }
+
+    /// The palette after this one in `PALETTES`, wrapping around.
+    pub fn next(&self) -> Self {
+        let index = PALETTES.iter().position(|palette| palette.name == self.name).unwrap_or(0);
+        PALETTES[(index + 1) % PALETTES.len()]
+    }
+
+    /// The colour of a pixel holding the bitplane mask `pixel`.
+    pub fn color(&self, pixel: u8) -> [u8; 4] {
+        self.colors[pixel as usize & 0x3]
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        PALETTES[0]
+    }
+}
@@ -0,0 +1,226 @@
+use std::collections::VecDeque;
+use palette::Palette;
+
+pub const DEFAULT_DECAY: f32 = 0.6;
+pub const DEFAULT_OR_FRAMES: usize = 2;
+
+/// How pixels that were just switched off linger on screen. Sprites are
+/// erased and redrawn with XOR, so without persistence anything that moves
+/// spends part of its time invisible and flickers.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum PhosphorMode {
+    #[default]
+    Off,
+    /// Lit pixels show at once; dark ones fade, keeping this fraction of
+    /// their previous colour every frame (0 to 1).
+    Decay(f32),
+    /// Each pixel shows the OR of its bitplanes over this many frames.
+    Or(usize),
+}
+
+impl PhosphorMode {
+    /// Parses `off`, `decay`, `decay:STRENGTH`, `or` or `or:FRAMES`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        let mut parts = name.splitn(2, ':');
+        let mode = parts.next().unwrap_or("");
+        let strength = parts.next();
+        match (mode, strength) {
+            ("off", None) => Some(PhosphorMode::Off),
+            ("decay", None) => Some(PhosphorMode::Decay(DEFAULT_DECAY)),
+            ("decay", Some(strength)) => match strength.parse() {
+                Ok(decay) if (0.0..1.0).contains(&decay) => Some(PhosphorMode::Decay(decay)),
+                _ => None,
+            },
+            ("or", None) => Some(PhosphorMode::Or(DEFAULT_OR_FRAMES)),
+            ("or", Some(frames)) => match frames.parse() {
+                Ok(frames) if frames > 0 => Some(PhosphorMode::Or(frames)),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+}
+
+/// Turns framebuffers into colours, applying the persistence mode. Fed once
+/// per presented frame; only redoes the work that the dirty rows, a palette
+/// change or a fade still in progress call for.
+pub struct Phosphor {
+    pub mode: PhosphorMode,
+    width: usize,
+    height: usize,
+    palette: Option<Palette>,
+    glow: Vec<[f32; 4]>,
+    history: VecDeque<Vec<Vec<u8>>>,
+    /// Updates in a row with nothing drawn.
+    quiet: usize,
+    /// Whether a decaying pixel has yet to reach the background.
+    fading: bool,
+    colors: Vec<[u8; 4]>,
+}
+
+impl Phosphor {
+    pub fn new(mode: PhosphorMode) -> Self {
+        Phosphor {
+            mode,
+            width: 0,
+            height: 0,
+            palette: None,
+            glow: Vec::new(),
+            history: VecDeque::new(),
+            quiet: 0,
+            fading: false,
+            colors: Vec::new(),
+        }
+    }
+
+    /// Colours from the last `update`, row by row.
+    pub fn colors(&self) -> &[[u8; 4]] {
+        &self.colors
+    }
+
+    /// Recomputes the colours for `vram`, whose changed rows are flagged in
+    /// `dirty`. Returns whether any colour may have changed.
+    pub fn update(&mut self, vram: &[Vec<u8>], dirty: &[bool], palette: &Palette) -> bool {
+        let height = vram.len();
+        let width = vram.first().map_or(0, |row| row.len());
+        let mut all_dirty = self.palette != Some(*palette);
+        if width != self.width || height != self.height {
+            self.width = width;
+            self.height = height;
+            self.glow.clear();
+            self.history.clear();
+            self.colors = vec![palette.color(0); width * height];
+            all_dirty = true;
+        }
+        self.palette = Some(*palette);
+        let drawn = all_dirty || dirty.iter().any(|&dirty| dirty);
+        self.quiet = if drawn { 0 } else { self.quiet + 1 };
+
+        match self.mode {
+            PhosphorMode::Off => {
+                for (y, row) in vram.iter().enumerate() {
+                    if all_dirty || dirty[y] {
+                        let colors = &mut self.colors[y * width..(y + 1) * width];
+                        for (color, &pixel) in colors.iter_mut().zip(row.iter()) {
+                            *color = palette.color(pixel);
+                        }
+                    }
+                }
+                drawn
+            }
+            PhosphorMode::Decay(decay) => {
+                if !drawn && !self.fading {
+                    return false;
+                }
+                if self.glow.len() != width * height {
+                    self.glow = vec![float_color(palette.color(0)); width * height];
+                }
+                let mut fading = false;
+                let pixels = vram.iter().flat_map(|row| row.iter());
+                for ((glow, color), &pixel) in self.glow.iter_mut().zip(self.colors.iter_mut()).zip(pixels) {
+                    let target = float_color(palette.color(pixel));
+                    if pixel != 0 {
+                        *glow = target;
+                    } else {
+                        for (channel, target) in glow.iter_mut().zip(target.iter()) {
+                            *channel = target + (*channel - target) * decay;
+                            if (*channel - target).abs() < 0.5 {
+                                *channel = *target;
+                            } else {
+                                fading = true;
+                            }
+                        }
+                    }
+                    *color = byte_color(*glow);
+                }
+                self.fading = fading;
+                true
+            }
+            PhosphorMode::Or(frames) => {
+                if self.quiet >= frames {
+                    return false;
+                }
+                self.history.push_back(vram.to_vec());
+                while self.history.len() > frames {
+                    self.history.pop_front();
+                }
+                for y in 0..height {
+                    for x in 0..width {
+                        let pixel = self.history.iter().fold(0, |mask, frame| mask | frame[y][x]);
+                        self.colors[y * width + x] = palette.color(pixel);
+                    }
+                }
+                true
+            }
+        }
+    }
+}
+
+impl Default for Phosphor {
+    fn default() -> Self {
+        Self::new(PhosphorMode::Off)
+    }
+}
+
+fn float_color(rgba: [u8; 4]) -> [f32; 4] {
+    [rgba[0] as f32, rgba[1] as f32, rgba[2] as f32, rgba[3] as f32]
+}
+
+fn byte_color(rgba: [f32; 4]) -> [u8; 4] {
+    [rgba[0].round() as u8, rgba[1].round() as u8, rgba[2].round() as u8, rgba[3].round() as u8]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIT: [[u8; 2]; 1] = [[1, 0]];
+    const DARK: [[u8; 2]; 1] = [[0, 0]];
+
+    fn vram(rows: [[u8; 2]; 1]) -> Vec<Vec<u8>> {
+        rows.iter().map(|row| row.to_vec()).collect()
+    }
+
+    #[test]
+    fn decay_fades_to_the_background() {
+        let palette = Palette::default();
+        let mut phosphor = Phosphor::new(PhosphorMode::Decay(0.5));
+        assert!(phosphor.update(&vram(LIT), &[true], &palette));
+        assert_eq!(phosphor.colors()[0], palette.color(1));
+        assert!(phosphor.update(&vram(DARK), &[true], &palette));
+
+        let background = palette.color(0);
+        let distance = |color: [u8; 4]| -> i32 {
+            color.iter().zip(background.iter()).map(|(&a, &b)| (a as i32 - b as i32).abs()).sum()
+        };
+        let mut previous = distance(phosphor.colors()[0]);
+        assert!(previous > 0);
+        let mut updates = 1;
+        while phosphor.update(&vram(DARK), &[false], &palette) {
+            let current = distance(phosphor.colors()[0]);
+            assert!(current <= previous);
+            previous = current;
+            updates += 1;
+            assert!(updates < 20, "still fading after {} updates", updates);
+        }
+        assert!(updates > 3);
+        assert_eq!(phosphor.colors(), &[background, background]);
+        assert!(!phosphor.update(&vram(DARK), &[false], &palette));
+    }
+
+    #[test]
+    fn or_keeps_pixels_for_its_frames_then_idles() {
+        let palette = Palette::default();
+        let mut phosphor = Phosphor::new(PhosphorMode::Or(3));
+        assert!(phosphor.update(&vram(LIT), &[true], &palette));
+        assert!(phosphor.update(&vram(DARK), &[true], &palette));
+        assert_eq!(phosphor.colors()[0], palette.color(1));
+        assert!(phosphor.update(&vram(DARK), &[false], &palette));
+        assert_eq!(phosphor.colors()[0], palette.color(1));
+        assert!(phosphor.update(&vram(DARK), &[false], &palette));
+        assert_eq!(phosphor.colors()[0], palette.color(0));
+        assert!(!phosphor.update(&vram(DARK), &[false], &palette));
+        assert!(!phosphor.update(&vram(DARK), &[false], &palette));
+        assert!(phosphor.update(&vram(LIT), &[true], &palette));
+    }
+}
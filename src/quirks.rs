@@ -0,0 +1,134 @@
+/// Toggles for the instructions whose behaviour differs between the
+/// interpreters CHIP-8 programs were written for.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Quirks {
+    /// `8xy6`/`8xyE` shift Vy into Vx instead of shifting Vx in place.
+    pub shift_uses_vy: bool,
+    /// `Fx55`/`Fx65` leave I pointing past the last register transferred.
+    pub load_store_increments_i: bool,
+    /// `Fx55`/`Fx65` leave I pointing at the last register transferred,
+    /// advancing it by x (CHIP-48). `load_store_increments_i` wins if both
+    /// are set.
+    pub load_store_increments_i_by_x: bool,
+    /// `Bnnn` jumps to `nnn + Vx` (x being the high nibble of nnn) instead of `nnn + V0`.
+    pub jump_uses_vx: bool,
+    /// `8xy1`, `8xy2` and `8xy3` reset VF to zero.
+    pub logic_resets_vf: bool,
+    /// Sprites drawn past the screen edges wrap around instead of being clipped.
+    pub wrap_sprites: bool,
+    /// `Fx0A` waits for a key to be pressed and then released, rather than
+    /// taking whichever key is already held down.
+    pub key_wait_on_release: bool,
+}
+
+pub const QUIRK_PRESETS: [&str; 4] = ["vip", "chip48", "schip", "xochip"];
+
+/// Number of toggles in `Quirks`.
+const QUIRK_COUNT: usize = 7;
+
+impl Quirks {
+    /// The original COSMAC VIP interpreter.
+    pub fn vip() -> Self {
+        Quirks {
+            shift_uses_vy: true,
+            load_store_increments_i: true,
+            load_store_increments_i_by_x: false,
+            jump_uses_vx: false,
+            logic_resets_vf: true,
+            wrap_sprites: false,
+            key_wait_on_release: true,
+        }
+    }
+
+    /// CHIP-48 for the HP-48 calculators.
+    pub fn chip48() -> Self {
+        Quirks {
+            shift_uses_vy: false,
+            load_store_increments_i: false,
+            load_store_increments_i_by_x: true,
+            jump_uses_vx: true,
+            logic_resets_vf: false,
+            wrap_sprites: false,
+            key_wait_on_release: false,
+        }
+    }
+
+    /// SUPER-CHIP 1.1.
+    pub fn schip() -> Self {
+        Quirks {
+            shift_uses_vy: false,
+            load_store_increments_i: false,
+            load_store_increments_i_by_x: false,
+            jump_uses_vx: true,
+            logic_resets_vf: false,
+            wrap_sprites: false,
+            key_wait_on_release: false,
+        }
+    }
+
+    /// XO-CHIP as implemented by Octo.
+    pub fn xochip() -> Self {
+        Quirks {
+            shift_uses_vy: true,
+            load_store_increments_i: true,
+            load_store_increments_i_by_x: false,
+            jump_uses_vx: false,
+            logic_resets_vf: false,
+            wrap_sprites: true,
+            key_wait_on_release: true,
+        }
+    }
+
+    /// Looks up a preset by the name used on the command line.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "vip" | "cosmac" => Some(Self::vip()),
+            "chip48" | "chip-48" => Some(Self::chip48()),
+            "schip" | "superchip" | "super-chip" => Some(Self::schip()),
+            "xochip" | "xo-chip" => Some(Self::xochip()),
+            _ => None,
+        }
+    }
+
+    /// Names of the quirks turned on, as written in movie files.
+    pub fn enabled(&self) -> Vec<&'static str> {
+        let mut quirks = *self;
+        quirks.toggles().iter().filter(|toggle| *toggle.1).map(|toggle| toggle.0).collect()
+    }
+
+    /// The quirks with exactly the named ones turned on, or `None` if a name
+    /// is unknown.
+    pub fn from_enabled(names: &[&str]) -> Option<Self> {
+        let mut quirks = Quirks {
+            shift_uses_vy: false,
+            load_store_increments_i: false,
+            load_store_increments_i_by_x: false,
+            jump_uses_vx: false,
+            logic_resets_vf: false,
+            wrap_sprites: false,
+            key_wait_on_release: false,
+        };
+        for name in names {
+            *quirks.toggles().iter_mut().find(|&&mut (toggle, _)| toggle == *name)?.1 = true;
+        }
+        Some(quirks)
+    }
+
+    fn toggles(&mut self) -> [(&'static str, &mut bool); QUIRK_COUNT] {
+        [
+            ("shift_uses_vy", &mut self.shift_uses_vy),
+            ("load_store_increments_i", &mut self.load_store_increments_i),
+            ("load_store_increments_i_by_x", &mut self.load_store_increments_i_by_x),
+            ("jump_uses_vx", &mut self.jump_uses_vx),
+            ("logic_resets_vf", &mut self.logic_resets_vf),
+            ("wrap_sprites", &mut self.wrap_sprites),
+            ("key_wait_on_release", &mut self.key_wait_on_release),
+        ]
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Self::vip()
+    }
+}
@@ -0,0 +1,193 @@
+use std::collections::VecDeque;
+use machine::TIMER_FREQ;
+
+/// Three minutes of history at one snapshot per frame.
+pub const REWIND_CAPACITY: usize = 3 * 60 * TIMER_FREQ as usize;
+
+/// Ring buffer of machine snapshots for stepping backwards in time.
+///
+/// Only the newest snapshot is kept whole. Every older one is stored as the
+/// run-length encoded XOR against the snapshot that followed it, which is
+/// almost entirely zeros since a frame touches very little of the machine.
+pub struct Rewind {
+    pub capacity: usize,
+    latest: Option<Vec<u8>>,
+    history: VecDeque<Entry>,
+}
+
+enum Entry {
+    /// XOR against the next newer snapshot.
+    Delta(Vec<u8>),
+    /// The snapshot itself, used when its size differs from the next one.
+    Full(Vec<u8>),
+}
+
+impl Rewind {
+    pub fn new(capacity: usize) -> Self {
+        Rewind {
+            capacity,
+            latest: None,
+            history: VecDeque::new(),
+        }
+    }
+
+    pub fn push(&mut self, snapshot: Vec<u8>) {
+        if let Some(previous) = self.latest.take() {
+            let entry = if previous.len() == snapshot.len() {
+                let delta: Vec<u8> = previous.iter().zip(snapshot.iter()).map(|(a, b)| a ^ b).collect();
+                Entry::Delta(compress(&delta))
+            } else {
+                Entry::Full(compress(&previous))
+            };
+            self.history.push_back(entry);
+            while self.history.len() > self.capacity {
+                self.history.pop_front();
+            }
+        }
+        self.latest = Some(snapshot);
+    }
+
+    /// Steps back one snapshot. The newest snapshot is the state the machine
+    /// is already in, so it is dropped and the one before it returned, staying
+    /// in the buffer as the newest. Returns `None` once only the oldest
+    /// snapshot is left.
+    pub fn pop(&mut self) -> Option<Vec<u8>> {
+        let previous = {
+            let latest = self.latest.as_ref()?;
+            match self.history.pop_back()? {
+                Entry::Delta(delta) => decompress(&delta).iter().zip(latest.iter()).map(|(a, b)| a ^ b).collect(),
+                Entry::Full(snapshot) => decompress(&snapshot),
+            }
+        };
+        self.latest = Some(previous.clone());
+        Some(previous)
+    }
+
+    pub fn len(&self) -> usize {
+        self.history.len() + self.latest.iter().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.latest.is_none()
+    }
+
+    pub fn clear(&mut self) {
+        self.latest = None;
+        self.history.clear();
+    }
+}
+
+impl Default for Rewind {
+    fn default() -> Self {
+        Self::new(REWIND_CAPACITY)
+    }
+}
+
+/// Encodes `data` as a sequence of (zero run, literal length, literals).
+fn compress(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut pos = 0;
+
+    while pos < data.len() {
+        let zeros = data[pos..].iter().take_while(|&&byte| byte == 0).count();
+        pos += zeros;
+        let literals = data[pos..].iter().take_while(|&&byte| byte != 0).count();
+        write_varint(&mut out, zeros);
+        write_varint(&mut out, literals);
+        out.extend_from_slice(&data[pos..pos + literals]);
+        pos += literals;
+    }
+
+    out
+}
+
+fn decompress(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut pos = 0;
+
+    while pos < data.len() {
+        let zeros = read_varint(data, &mut pos);
+        let literals = read_varint(data, &mut pos);
+        out.resize(out.len() + zeros, 0);
+        out.extend_from_slice(&data[pos..pos + literals]);
+        pos += literals;
+    }
+
+    out
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        out.push((value as u8 & 0x7F) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(data: &[u8], pos: &mut usize) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = data[*pos];
+        *pos += 1;
+        value |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return value;
+        }
+        shift += 7;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Mostly unchanging snapshots like the machine's, with the odd byte
+    /// changed and every fourth one a different size.
+    fn snapshot(n: usize) -> Vec<u8> {
+        let mut data = vec![0x55; if n % 4 == 3 { 300 } else { 200 }];
+        data[0] = n as u8;
+        data[100 + n % 50] ^= 0xFF;
+        data[150..160].copy_from_slice(&[0; 10]);
+        data
+    }
+
+    #[test]
+    fn pops_back_through_pushed_snapshots() {
+        let mut rewind = Rewind::new(100);
+        for n in 0..10 {
+            rewind.push(snapshot(n));
+        }
+        assert_eq!(rewind.len(), 10);
+        for n in (0..9).rev() {
+            assert_eq!(rewind.pop(), Some(snapshot(n)));
+        }
+        assert_eq!(rewind.pop(), None);
+        assert_eq!(rewind.len(), 1);
+
+        rewind.push(snapshot(20));
+        assert_eq!(rewind.pop(), Some(snapshot(0)));
+    }
+
+    #[test]
+    fn drops_the_oldest_snapshots_at_capacity() {
+        let mut rewind = Rewind::new(3);
+        for n in 0..8 {
+            rewind.push(snapshot(n));
+        }
+        assert_eq!(rewind.len(), 4);
+        for n in (4..7).rev() {
+            assert_eq!(rewind.pop(), Some(snapshot(n)));
+        }
+        assert_eq!(rewind.pop(), None);
+    }
+
+    #[test]
+    fn compression_round_trips() {
+        let cases: [&[u8]; 5] = [&[], &[0; 1000], &[1, 2, 3], &[0, 0, 7, 0, 9, 9], &[0xFF; 300]];
+        for data in cases.iter() {
+            assert_eq!(decompress(&compress(data)), data.to_vec());
+        }
+        assert!(compress(&[0; 1000]).len() < 4);
+    }
+}
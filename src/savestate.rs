@@ -0,0 +1,285 @@
+use cpu::Cpu;
+use memory::Memory;
+use error::Chip8Error;
+use machine::{CHIP8_WIDTH, CHIP8_HEIGHT, SCHIP_WIDTH, SCHIP_HEIGHT, VIP_HIRES_WIDTH, VIP_HIRES_HEIGHT};
+
+pub const SAVE_STATE_MAGIC: &[u8; 4] = b"C8ST";
+pub const SAVE_STATE_VERSION: u8 = 3;
+
+/// Framebuffer sizes the interpreter can switch to.
+const VRAM_SIZES: [(usize, usize); 3] = [
+    (CHIP8_WIDTH, CHIP8_HEIGHT),
+    (SCHIP_WIDTH, SCHIP_HEIGHT),
+    (VIP_HIRES_WIDTH, VIP_HIRES_HEIGHT),
+];
+
+/// Serializes the whole machine (CPU registers, timers, key wait state, the
+/// random generator, ram, stack and vram) together with the frame counter.
+/// All multi-byte values are little endian.
+pub fn snapshot(cpu: &Cpu, memory: &Memory, frame: u64) -> Vec<u8> {
+    let mut data = Vec::with_capacity(memory.ram.len() + memory.width() * memory.height() + 128);
+
+    data.extend_from_slice(SAVE_STATE_MAGIC);
+    data.push(SAVE_STATE_VERSION);
+    data.extend_from_slice(&frame.to_le_bytes());
+
+    data.extend_from_slice(&cpu.v);
+    data.extend_from_slice(&cpu.i.to_le_bytes());
+    data.extend_from_slice(&cpu.pc.to_le_bytes());
+    data.push(cpu.sp);
+    data.push(cpu.delay_timer);
+    data.push(cpu.sound_timer);
+    data.push(cpu.halt as u8);
+    data.push(cpu.keypad_waiting as u8);
+    data.push(cpu.keypad_register);
+    data.extend_from_slice(&cpu.rpl);
+    data.push(cpu.plane);
+    data.push(cpu.audio_pattern.is_some() as u8);
+    data.extend_from_slice(&cpu.audio_pattern.unwrap_or_default());
+    data.push(cpu.pitch);
+    data.extend_from_slice(&cpu.seed.to_le_bytes());
+    let rng = cpu.rng.state();
+    data.extend_from_slice(&(rng.len() as u32).to_le_bytes());
+    data.extend_from_slice(&rng);
+
+    data.extend_from_slice(&(memory.ram.len() as u32).to_le_bytes());
+    data.extend_from_slice(&memory.ram);
+    for addr in memory.stack.iter() {
+        data.extend_from_slice(&addr.to_le_bytes());
+    }
+    data.extend_from_slice(&(memory.width() as u16).to_le_bytes());
+    data.extend_from_slice(&(memory.height() as u16).to_le_bytes());
+    for row in memory.vram.iter() {
+        data.extend_from_slice(row);
+    }
+
+    data
+}
+
+/// Restores a snapshot taken by `snapshot`, returning its frame counter. The
+/// machine is left untouched if the data is rejected, which includes values
+/// the interpreter could not have produced, such as a stack pointer past the
+/// end of the stack.
+pub fn restore(cpu: &mut Cpu, memory: &mut Memory, data: &[u8]) -> Result<u64, Chip8Error> {
+    let mut reader = Reader { data, pos: 0 };
+
+    if reader.bytes(4)? != SAVE_STATE_MAGIC {
+        return Err(invalid("not a save state"));
+    }
+    let version = reader.u8()?;
+    if version != SAVE_STATE_VERSION {
+        return Err(invalid(&format!("unsupported version {}", version)));
+    }
+    let frame = reader.u64()?;
+
+    let mut v = [0; 16];
+    v.copy_from_slice(reader.bytes(16)?);
+    let i = reader.u16()?;
+    let pc = reader.u16()?;
+    let sp = reader.u8()?;
+    let delay_timer = reader.u8()?;
+    let sound_timer = reader.u8()?;
+    let halt = reader.u8()? != 0;
+    let keypad_waiting = reader.u8()? != 0;
+    let keypad_register = reader.u8()?;
+    let mut rpl = [0; 16];
+    rpl.copy_from_slice(reader.bytes(16)?);
+    let plane = reader.u8()?;
+    let pattern_loaded = reader.u8()? != 0;
+    let mut audio_pattern = [0; 16];
+    audio_pattern.copy_from_slice(reader.bytes(16)?);
+    let audio_pattern = if pattern_loaded { Some(audio_pattern) } else { None };
+    let pitch = reader.u8()?;
+    let seed = reader.u64()?;
+    let rng_len = reader.u32()? as usize;
+    let rng = reader.bytes(rng_len)?;
+    if sp as usize > memory.stack.len() {
+        return Err(invalid(&format!("stack pointer {} past the end of the stack", sp)));
+    }
+    if keypad_register > 0xF {
+        return Err(invalid(&format!("no register V{:X} to wait for a key in", keypad_register)));
+    }
+    if plane > 3 {
+        return Err(invalid(&format!("no bitplane selection {}", plane)));
+    }
+
+    let ram_len = reader.u32()? as usize;
+    if ram_len != memory.ram.len() {
+        return Err(invalid(&format!("expected {} bytes of ram, found {}", memory.ram.len(), ram_len)));
+    }
+    let ram = reader.bytes(ram_len)?;
+    let mut stack = [0; 16];
+    for addr in stack.iter_mut() {
+        *addr = reader.u16()?;
+    }
+    let width = reader.u16()? as usize;
+    let height = reader.u16()? as usize;
+    if !VRAM_SIZES.contains(&(width, height)) {
+        return Err(invalid(&format!("unsupported {}x{} framebuffer", width, height)));
+    }
+    let mut vram = Vec::with_capacity(height);
+    for _ in 0..height {
+        vram.push(reader.bytes(width)?.to_vec());
+    }
+    if !cpu.rng.set_state(rng) {
+        return Err(invalid("random generator state does not match the --rng source"));
+    }
+
+    cpu.v = v;
+    cpu.i = i;
+    cpu.pc = pc;
+    cpu.sp = sp;
+    cpu.delay_timer = delay_timer;
+    cpu.sound_timer = sound_timer;
+    cpu.halt = halt;
+    cpu.keypad_waiting = keypad_waiting;
+    cpu.keypad_register = keypad_register;
+    cpu.keypad_key = None;
+    cpu.rpl = rpl;
+    cpu.plane = plane;
+    cpu.audio_pattern = audio_pattern;
+    cpu.pitch = pitch;
+    cpu.seed = seed;
+
+    memory.ram.copy_from_slice(ram);
+    memory.stack = stack;
+    memory.vram = vram;
+    memory.mark_all_dirty();
+
+    Ok(frame)
+}
+
+fn invalid(reason: &str) -> Chip8Error {
+    Chip8Error::InvalidSaveState(reason.to_string())
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], Chip8Error> {
+        let bytes = self.data.get(self.pos..self.pos + len).ok_or_else(|| invalid("truncated data"))?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, Chip8Error> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, Chip8Error> {
+        let mut buf = [0; 2];
+        buf.copy_from_slice(self.bytes(2)?);
+        Ok(u16::from_le_bytes(buf))
+    }
+
+    fn u32(&mut self) -> Result<u32, Chip8Error> {
+        let mut buf = [0; 4];
+        buf.copy_from_slice(self.bytes(4)?);
+        Ok(u32::from_le_bytes(buf))
+    }
+
+    fn u64(&mut self) -> Result<u64, Chip8Error> {
+        let mut buf = [0; 8];
+        buf.copy_from_slice(self.bytes(8)?);
+        Ok(u64::from_le_bytes(buf))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use machine::Machine;
+    use drivers::{Keyboard, NullAudio};
+    use quirks::Quirks;
+
+    // Offsets of single byte fields in a version 3 state.
+    const VERSION: usize = 4;
+    const SP: usize = 33;
+    const KEYPAD_REGISTER: usize = 38;
+    const PLANE: usize = 55;
+
+    /// A machine running `RND V0, 0xFF` in a loop.
+    fn machine(seed: u64) -> Machine<NullAudio, Keyboard> {
+        let mut machine = Machine::new(NullAudio, Keyboard::new(), Quirks::default());
+        machine.reseed(seed);
+        machine.load_program(&[0xC0, 0xFF, 0x12, 0x00]).unwrap();
+        machine
+    }
+
+    fn random_bytes(machine: &mut Machine<NullAudio, Keyboard>) -> Vec<u8> {
+        (0..8).map(|_| {
+            machine.run(2).unwrap();
+            machine.cpu.lock().unwrap().v[0]
+        }).collect()
+    }
+
+    /// The vram width, which follows the ram and the stack.
+    fn width_offset(data: &[u8]) -> usize {
+        data.len() - CHIP8_WIDTH * CHIP8_HEIGHT - 4
+    }
+
+    #[test]
+    fn round_trips_the_machine() {
+        let mut original = machine(7);
+        original.run(10).unwrap();
+        original.frame = 42;
+        original.memory.lock().unwrap().vram[3][5] = 1;
+        let state = original.save_state();
+        let expected = random_bytes(&mut original);
+
+        let mut restored = machine(8);
+        restored.load_state(&state).unwrap();
+        assert_eq!(restored.frame, 42);
+        assert_eq!(restored.save_state(), state);
+        assert_eq!(restored.memory.lock().unwrap().vram[3][5], 1);
+        assert_eq!(random_bytes(&mut restored), expected);
+    }
+
+    #[test]
+    fn rejects_other_files_and_versions() {
+        let mut machine = machine(1);
+        let mut state = machine.save_state();
+        state[VERSION] = SAVE_STATE_VERSION + 1;
+        assert!(machine.load_state(&state).is_err());
+        assert!(machine.load_state(b"PNG\x00 not a save state").is_err());
+        assert!(machine.load_state(&[]).is_err());
+        let state = machine.save_state();
+        assert!(machine.load_state(&state[..state.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn rejects_out_of_range_fields() {
+        let mut machine = machine(1);
+        machine.run(3).unwrap();
+        let valid = machine.save_state();
+
+        let mut corrupt = Vec::new();
+        for &(offset, value) in [(SP, 17), (KEYPAD_REGISTER, 0x10), (PLANE, 4)].iter() {
+            let mut state = valid.clone();
+            state[offset] = value;
+            corrupt.push(state);
+        }
+        for &(width, height) in [(100, 32), (128, 32), (0, 0)].iter() {
+            let mut state = valid.clone();
+            let offset = width_offset(&state);
+            state[offset..offset + 2].copy_from_slice(&(width as u16).to_le_bytes());
+            state[offset + 2..offset + 4].copy_from_slice(&(height as u16).to_le_bytes());
+            corrupt.push(state);
+        }
+
+        for state in corrupt {
+            match machine.load_state(&state) {
+                Err(Chip8Error::InvalidSaveState(_)) => {}
+                result => panic!("expected an invalid save state, got {:?}", result),
+            }
+            assert_eq!(machine.save_state(), valid);
+        }
+
+        let mut state = valid.clone();
+        state[SP] = 16;
+        assert!(machine.load_state(&state).is_ok());
+    }
+}
@@ -0,0 +1,53 @@
+use std::io::Write;
+use png::{self, HasParameters};
+use palette::Palette;
+use error::Chip8Error;
+
+/// Each CHIP-8 pixel becomes a square of this many image pixels.
+pub const SCREENSHOT_SCALE: usize = 4;
+
+/// Encodes `vram` as an RGBA PNG in the colours of `palette`.
+pub fn write_png<W: Write>(out: W, vram: &[Vec<u8>], palette: &Palette, scale: usize) -> Result<(), Chip8Error> {
+    let width = vram[0].len() * scale;
+    let height = vram.len() * scale;
+    let mut data = Vec::with_capacity(width * height * 4);
+    for row in vram {
+        let mut line = Vec::with_capacity(width * 4);
+        for &pixel in row {
+            for _ in 0..scale {
+                line.extend_from_slice(&palette.color(pixel));
+            }
+        }
+        for _ in 0..scale {
+            data.extend_from_slice(&line);
+        }
+    }
+
+    encode_png(out, width, height, &data)
+}
+
+/// Encodes packed RGBA bytes, row by row, as a PNG.
+pub fn encode_png<W: Write>(out: W, width: usize, height: usize, rgba: &[u8]) -> Result<(), Chip8Error> {
+    let mut encoder = png::Encoder::new(out, width as u32, height as u32);
+    encoder.set(png::ColorType::RGBA).set(png::BitDepth::Eight);
+    encoder.write_header()
+        .and_then(|mut writer| writer.write_image_data(rgba))
+        .map_err(|error| Chip8Error::Io(error.to_string()))
+}
+
+/// Encodes `vram` unscaled as a binary PBM, with every pixel lit on any
+/// bitplane set.
+pub fn write_pbm<W: Write>(mut out: W, vram: &[Vec<u8>]) -> Result<(), Chip8Error> {
+    let width = vram[0].len();
+    write!(out, "P4\n{} {}\n", width, vram.len())?;
+    for row in vram {
+        let mut bytes = vec![0; width.div_ceil(8)];
+        for (x, &pixel) in row.iter().enumerate() {
+            if pixel != 0 {
+                bytes[x / 8] |= 0x80 >> (x % 8);
+            }
+        }
+        out.write_all(&bytes)?;
+    }
+    Ok(())
+}
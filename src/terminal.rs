@@ -0,0 +1,308 @@
+use std::io::{self, Write};
+use std::thread;
+use std::time::{Duration, Instant};
+use termion::{async_stdin, clear, color, cursor};
+use termion::event::Key;
+use termion::input::TermRead;
+use termion::raw::IntoRawMode;
+use termion::screen::AlternateScreen;
+use hardware::{AudioDriver, DisplayDriver};
+use drivers::TerminalKeyboard;
+use palette::Palette;
+use phosphor::Phosphor;
+use machine::{Machine, CHIP8_WIDTH, CHIP8_HEIGHT, TIMER_FREQ};
+use instruction::decode;
+use debugger::DebugCommand;
+use config::{Hotkey, KeyBindings};
+use quirks::Quirks;
+
+/// Columns between the screen and the register panel.
+const PANEL_GAP: u16 = 3;
+
+/// Draws the framebuffer with half-block characters, packing two rows of
+/// pixels into each cell: the upper pixel is the foreground of `▀` and the
+/// lower one its background.
+pub struct TerminalDisplay {
+    pub palette: Palette,
+    pub phosphor: Phosphor,
+    width: usize,
+    height: usize,
+    colors: Vec<[u8; 4]>,
+    drawn: Vec<[u8; 4]>,
+    resized: bool,
+}
+
+impl TerminalDisplay {
+    pub fn new() -> Self {
+        TerminalDisplay {
+            palette: Palette::default(),
+            phosphor: Phosphor::default(),
+            width: CHIP8_WIDTH,
+            height: CHIP8_HEIGHT,
+            colors: Vec::new(),
+            drawn: Vec::new(),
+            resized: true,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Height in terminal rows.
+    pub fn rows(&self) -> usize {
+        self.height.div_ceil(2)
+    }
+
+    /// Writes the screen if it changed since the last call. Returns whether
+    /// the terminal was cleared, in which case everything else needs
+    /// redrawing too.
+    pub fn draw<W: Write>(&mut self, out: &mut W) -> io::Result<bool> {
+        let cleared = self.resized;
+        if self.resized {
+            write!(out, "{}{}", color::Bg(color::Reset), clear::All)?;
+            self.resized = false;
+        } else if self.colors == self.drawn {
+            return Ok(false);
+        }
+
+        let mut last = None;
+        for row in 0..self.rows() {
+            write!(out, "{}", cursor::Goto(1, row as u16 + 1))?;
+            for x in 0..self.width {
+                let top = self.colors[row * 2 * self.width + x];
+                let bottom = self.colors.get((row * 2 + 1) * self.width + x).cloned().unwrap_or(top);
+                if last != Some((top, bottom)) {
+                    write!(out, "{}{}", color::Fg(rgb(top)), color::Bg(rgb(bottom)))?;
+                    last = Some((top, bottom));
+                }
+                write!(out, "▀")?;
+            }
+        }
+        write!(out, "{}{}", color::Fg(color::Reset), color::Bg(color::Reset))?;
+        self.drawn.clone_from(&self.colors);
+        Ok(cleared)
+    }
+}
+
+impl Default for TerminalDisplay {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DisplayDriver for TerminalDisplay {
+    fn refresh(&mut self, vram: &[Vec<u8>], dirty: &[bool]) {
+        if !self.phosphor.update(vram, dirty, &self.palette) {
+            return;
+        }
+        let (width, height) = (vram[0].len(), vram.len());
+        self.resized |= width != self.width || height != self.height;
+        self.width = width;
+        self.height = height;
+        self.colors = self.phosphor.colors().to_vec();
+    }
+}
+
+/// Terminal frontend: plays a `Machine` at 60 Hz on stdout with a register
+/// panel next to the screen, reading the keypad from raw stdin.
+///
+/// Keys go through the configured bindings. Of the hotkeys, the palette,
+/// record and screenshot ones work here. Debugger bindings run their
+/// command and show its first line of output in the status line, next to
+/// the register panel. Tracing is left out as it would print over the
+/// screen.
+pub struct Tui<A>
+    where A: AudioDriver + Sync + Send,
+{
+    pub machine: Machine<A, TerminalKeyboard>,
+    pub display: TerminalDisplay,
+    pub bindings: KeyBindings,
+    pub status: String,
+}
+
+impl<A> Tui<A>
+    where A: AudioDriver + Sync + Send,
+{
+    pub fn new(audio: A, quirks: Quirks) -> Self {
+        Tui {
+            machine: Machine::new(audio, TerminalKeyboard::new(), quirks),
+            display: TerminalDisplay::new(),
+            bindings: KeyBindings::default(),
+            status: String::from("running"),
+        }
+    }
+
+    /// Runs until Esc or Ctrl-C is pressed.
+    pub fn boot(&mut self) -> io::Result<()> {
+        let mut out = AlternateScreen::from(io::stdout().into_raw_mode()?);
+        write!(out, "{}", cursor::Hide)?;
+        let mut keys = async_stdin().keys();
+        let frame_time = Duration::from_secs(1) / TIMER_FREQ as u32;
+        let mut next_frame = Instant::now();
+
+        'running: loop {
+            for key in keys.by_ref() {
+                match key? {
+                    Key::Esc | Key::Ctrl('c') => break 'running,
+                    key => self.input(key),
+                }
+            }
+
+            if !self.machine.debugger.paused {
+                for key in self.machine.keyboard.expire() {
+                    self.machine.release_key(key);
+                }
+                if let Err(error) = self.machine.frame() {
+                    self.machine.debugger.pause(error.to_string());
+                }
+            }
+            if let Some(reason) = self.machine.debugger.stopped.take() {
+                self.status = reason;
+            }
+            if let Some(error) = self.machine.video_error.take() {
+                self.status = format!("stopped recording: {}", error);
+            }
+
+            self.machine.present(&mut self.display);
+            let cleared = self.display.draw(&mut out)?;
+            self.draw_panel(&mut out, cleared)?;
+            out.flush()?;
+
+            next_frame += frame_time;
+            let now = Instant::now();
+            if next_frame > now {
+                thread::sleep(next_frame - now);
+            } else {
+                next_frame = now;
+            }
+        }
+
+        write!(out, "{}", cursor::Show)?;
+        out.flush()
+    }
+
+    fn input(&mut self, key: Key) {
+        let name = match key_name(key) {
+            Some(name) => name,
+            None => return,
+        };
+
+        if let Some(hex) = self.bindings.keypad_key(&name) {
+            self.machine.press_key(hex);
+            return;
+        }
+        if let Some(hotkey) = self.bindings.hotkey(&name) {
+            self.hotkey(hotkey);
+            return;
+        }
+        match self.bindings.debug_command(&name) {
+            Some(DebugCommand::Trace(_)) | None => {}
+            Some(command) => {
+                let output = self.machine.debug(command);
+                self.status = output.lines().next().unwrap_or("").to_string();
+            }
+        }
+    }
+
+    /// Carries out the hotkeys that make sense without key releases: the
+    /// palette, recording and screenshots.
+    fn hotkey(&mut self, hotkey: Hotkey) {
+        match hotkey {
+            Hotkey::Palette => {
+                self.display.palette = self.display.palette.next();
+                self.status = format!("palette {}", self.display.palette.name);
+            }
+            Hotkey::Record => self.toggle_video(),
+            Hotkey::Screenshot => {
+                self.status = match self.machine.save_screenshot(&self.display.palette) {
+                    Ok(paths) => format!("saved {}", paths.join(", ")),
+                    Err(error) => error.to_string(),
+                };
+            }
+            _ => {}
+        }
+    }
+
+    fn toggle_video(&mut self) {
+        self.status = if self.machine.video.is_some() {
+            match self.machine.stop_video() {
+                Ok(path) => format!("saved {}", path.unwrap_or_default()),
+                Err(error) => error.to_string(),
+            }
+        } else {
+            let path = self.machine.video_path();
+            match self.machine.start_video(&path, &self.display.palette) {
+                Ok(()) => format!("recording {}", path),
+                Err(error) => format!("{}: {}", path, error),
+            }
+        };
+    }
+
+    fn draw_panel<W: Write>(&self, out: &mut W, cleared: bool) -> io::Result<()> {
+        let column = self.display.width() as u16 + PANEL_GAP;
+        let cpu = self.machine.cpu.lock().unwrap();
+        let opcode = cpu.opcode();
+
+        let mut lines = vec![
+            format!("PC {:04X}  {}", cpu.pc, decode(opcode)),
+            format!("I  {:04X}  SP {:X}", cpu.i, cpu.sp),
+            format!("DT {:02X}    ST {:02X}", cpu.delay_timer, cpu.sound_timer),
+            String::new(),
+        ];
+        for (row, registers) in cpu.v.chunks(4).enumerate() {
+            let cells: Vec<String> = registers.iter().enumerate()
+                .map(|(i, value)| format!("V{:X} {:02X}", row * 4 + i, value))
+                .collect();
+            lines.push(cells.join("  "));
+        }
+        lines.push(String::new());
+        lines.push(format!("frame {}  ipf {}", self.machine.frame, self.machine.instructions_per_frame));
+        lines.push(self.status.clone());
+
+        for (row, line) in lines.iter().enumerate() {
+            write!(out, "{}{}{}", cursor::Goto(column, row as u16 + 1), line, clear::UntilNewline)?;
+        }
+        if cleared {
+            let row = self.display.rows().max(lines.len() + 2) as u16;
+            let actions = [(Hotkey::Palette, "palette"), (Hotkey::Record, "record"), (Hotkey::Screenshot, "screenshot")];
+            let mut help: Vec<String> = actions.iter()
+                .filter_map(|&(hotkey, action)| self.bindings.hotkey_name(hotkey).map(|key| format!("{} {}", key, action)))
+                .collect();
+            help.push(String::from("Esc quit"));
+            write!(out, "{}{}", cursor::Goto(column, row), help.join("  "))?;
+        }
+        Ok(())
+    }
+}
+
+/// The name a key has in the key bindings, following the window backend's
+/// names so one config serves both frontends.
+pub fn key_name(key: Key) -> Option<String> {
+    let name = match key {
+        Key::Char(' ') => "Space",
+        Key::Char('\t') => "Tab",
+        Key::Char('\n') => "Return",
+        Key::Char('-') => "Minus",
+        Key::Char('=') => "Equals",
+        Key::Char(',') => "Comma",
+        Key::Char('.') => "Period",
+        Key::Char('/') => "Slash",
+        Key::Char(';') => "Semicolon",
+        Key::Char('`') => "Backquote",
+        Key::Char(c) if c.is_ascii_digit() => return Some(format!("D{}", c)),
+        Key::Char(c) if c.is_ascii_alphabetic() => return Some(c.to_ascii_uppercase().to_string()),
+        Key::F(n) => return Some(format!("F{}", n)),
+        Key::Up => "Up",
+        Key::Down => "Down",
+        Key::Left => "Left",
+        Key::Right => "Right",
+        Key::Backspace => "Backspace",
+        _ => return None,
+    };
+    Some(name.to_string())
+}
+
+fn rgb([r, g, b, _]: [u8; 4]) -> color::Rgb {
+    color::Rgb(r, g, b)
+}
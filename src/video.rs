@@ -0,0 +1,162 @@
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use gif::{self, SetParameter};
+use audio::AUDIO_SAMPLE_RATE;
+use drivers::WavWriter;
+use error::Chip8Error;
+use hardware::AudioDriver;
+use machine::{SCHIP_WIDTH, SCHIP_HEIGHT, TIMER_FREQ};
+use palette::Palette;
+use screenshot;
+
+/// Recordings are drawn on a canvas this many times the SCHIP resolution, so
+/// lores and hires frames come out the same size.
+pub const VIDEO_SCALE: usize = 4;
+pub const VIDEO_WIDTH: usize = SCHIP_WIDTH * VIDEO_SCALE;
+pub const VIDEO_HEIGHT: usize = SCHIP_HEIGHT * VIDEO_SCALE;
+
+/// Viewers play GIF frames shorter than this, in hundredths of a second, at a
+/// crawl, so briefer frames are merged into the next one.
+const MIN_GIF_DELAY: u64 = 2;
+
+/// Records the machine's frames as they are emulated, one per 60 Hz frame
+/// however many instructions run in it, so the result plays at game speed.
+///
+/// A path ending in `.gif` gets an animated GIF. Anything else is taken as a
+/// directory, which receives numbered PNG frames and the sound as
+/// `audio.wav`.
+pub struct VideoRecorder {
+    pub path: String,
+    palette: Palette,
+    frame: u64,
+    pixels: Vec<u8>,
+    sink: Sink,
+}
+
+enum Sink {
+    Gif {
+        encoder: gif::Encoder<BufWriter<File>>,
+        /// The frame waiting for its duration to be known, and the frame
+        /// number it first showed on.
+        pending: Option<(Vec<u8>, u64)>,
+    },
+    Frames {
+        dir: PathBuf,
+        audio: WavWriter,
+    },
+}
+
+impl VideoRecorder {
+    pub fn create(path: &str, palette: &Palette) -> Result<Self, Chip8Error> {
+        let sink = if path.to_ascii_lowercase().ends_with(".gif") {
+            let colors: Vec<u8> = palette.colors.iter().flat_map(|color| color[..3].to_vec()).collect();
+            let file = BufWriter::new(File::create(path)?);
+            let mut encoder = gif::Encoder::new(file, VIDEO_WIDTH as u16, VIDEO_HEIGHT as u16, &colors)?;
+            encoder.set(gif::Repeat::Infinite)?;
+            Sink::Gif { encoder, pending: None }
+        } else {
+            let dir = PathBuf::from(path);
+            fs::create_dir_all(&dir)?;
+            let audio = WavWriter::create(dir.join("audio.wav"), AUDIO_SAMPLE_RATE)?;
+            Sink::Frames { dir, audio }
+        };
+
+        Ok(VideoRecorder {
+            path: path.to_string(),
+            palette: *palette,
+            frame: 0,
+            pixels: Vec::with_capacity(VIDEO_WIDTH * VIDEO_HEIGHT),
+            sink,
+        })
+    }
+
+    /// Adds one emulated frame: its framebuffer and the samples played
+    /// during it.
+    pub fn capture(&mut self, vram: &[Vec<u8>], samples: &[i16]) -> Result<(), Chip8Error> {
+        self.draw(vram);
+        let frame = self.frame;
+        self.frame += 1;
+
+        match self.sink {
+            Sink::Gif { ref mut encoder, ref mut pending } => {
+                let start = match pending.take() {
+                    Some((pixels, start)) if pixels == self.pixels => {
+                        *pending = Some((pixels, start));
+                        return Ok(());
+                    }
+                    Some((pixels, start)) => {
+                        if gif_delay(start, frame) >= MIN_GIF_DELAY {
+                            write_gif_frame(encoder, &pixels, gif_delay(start, frame))?;
+                            frame
+                        } else {
+                            start
+                        }
+                    }
+                    None => frame,
+                };
+                *pending = Some((self.pixels.clone(), start));
+            }
+            Sink::Frames { ref dir, ref mut audio } => {
+                let path = dir.join(format!("frame-{:06}.png", frame));
+                write_png_frame(&path, &self.pixels, &self.palette)?;
+                audio.queue(samples);
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes out whatever is still buffered and completes the files.
+    pub fn finish(mut self) -> Result<(), Chip8Error> {
+        match self.sink {
+            Sink::Gif { ref mut encoder, ref mut pending } => {
+                if let Some((pixels, start)) = pending.take() {
+                    let delay = gif_delay(start, self.frame).max(MIN_GIF_DELAY);
+                    write_gif_frame(encoder, &pixels, delay)?;
+                }
+            }
+            Sink::Frames { ref mut audio, .. } => audio.finish()?,
+        }
+        Ok(())
+    }
+
+    /// Scales `vram` onto the canvas as palette indices.
+    fn draw(&mut self, vram: &[Vec<u8>]) {
+        let scale_x = VIDEO_WIDTH / vram[0].len();
+        let scale_y = VIDEO_HEIGHT / vram.len();
+        self.pixels.clear();
+        for row in vram {
+            let start = self.pixels.len();
+            for &pixel in row {
+                for _ in 0..scale_x {
+                    self.pixels.push(pixel & 0x3);
+                }
+            }
+            for _ in 1..scale_y {
+                self.pixels.extend_from_within(start..start + VIDEO_WIDTH);
+            }
+        }
+    }
+}
+
+/// Hundredths of a second between two frames, rounded on the frame clock so
+/// the delays add up without drifting.
+fn gif_delay(start: u64, end: u64) -> u64 {
+    let centis = |frame: u64| (frame * 100 + TIMER_FREQ / 2) / TIMER_FREQ;
+    centis(end) - centis(start)
+}
+
+fn write_gif_frame(encoder: &mut gif::Encoder<BufWriter<File>>, pixels: &[u8], delay: u64) -> Result<(), Chip8Error> {
+    let mut frame = gif::Frame::from_indexed_pixels(VIDEO_WIDTH as u16, VIDEO_HEIGHT as u16, pixels, None);
+    frame.delay = delay.min(u16::MAX as u64) as u16;
+    encoder.write_frame(&frame)?;
+    Ok(())
+}
+
+fn write_png_frame(path: &Path, pixels: &[u8], palette: &Palette) -> Result<(), Chip8Error> {
+    let rgba: Vec<u8> = pixels.iter().flat_map(|&pixel| palette.color(pixel).to_vec()).collect();
+    let mut file = BufWriter::new(File::create(path)?);
+    screenshot::encode_png(&mut file, VIDEO_WIDTH, VIDEO_HEIGHT, &rgba)?;
+    file.flush()?;
+    Ok(())
+}
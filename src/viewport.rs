@@ -0,0 +1,93 @@
+/// Largest `--scale`, which already makes a 4096x2048 window.
+pub const MAX_SCALE: usize = 64;
+
+/// How the framebuffer is scaled to the window.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ScaleMode {
+    /// Whole multiples of the native size only, so every pixel is drawn the
+    /// same size. Falls back to `Fit` in windows too small for that.
+    #[default]
+    Integer,
+    /// As large as fits, keeping the aspect ratio.
+    Fit,
+}
+
+impl ScaleMode {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "integer" => Some(ScaleMode::Integer),
+            "fit" => Some(ScaleMode::Fit),
+            _ => None,
+        }
+    }
+}
+
+/// Where the framebuffer goes in the window: the top left corner of the
+/// screen and the size of one pixel, in window coordinates. The rest of the
+/// window is letterboxing.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Viewport {
+    pub x: f64,
+    pub y: f64,
+    pub pixel_width: f64,
+    pub pixel_height: f64,
+}
+
+impl Viewport {
+    /// Lays out a `width` by `height` framebuffer in a window of the given
+    /// size. The CHIP-8 screen is always twice as wide as it is tall, so the
+    /// pixels of the VIP's 64x64 mode come out twice as wide as they are
+    /// high.
+    pub fn fit(width: usize, height: usize, window_width: f64, window_height: f64, mode: ScaleMode) -> Self {
+        let aspect = (2 * height) as f64 / width as f64;
+        let fit = (window_width / (width as f64 * aspect)).min(window_height / height as f64);
+        let pixel_height = match mode {
+            ScaleMode::Integer if fit >= 1.0 => fit.floor(),
+            _ => fit,
+        };
+        let pixel_width = pixel_height * aspect;
+
+        Viewport {
+            x: ((window_width - pixel_width * width as f64) / 2.0).floor(),
+            y: ((window_height - pixel_height * height as f64) / 2.0).floor(),
+            pixel_width,
+            pixel_height,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn viewport(x: f64, y: f64, pixel_width: f64, pixel_height: f64) -> Viewport {
+        Viewport { x, y, pixel_width, pixel_height }
+    }
+
+    #[test]
+    fn integer_scaling_keeps_whole_pixels() {
+        assert_eq!(Viewport::fit(64, 32, 700.0, 400.0, ScaleMode::Integer), viewport(30.0, 40.0, 10.0, 10.0));
+        assert_eq!(Viewport::fit(128, 64, 700.0, 400.0, ScaleMode::Integer), viewport(30.0, 40.0, 5.0, 5.0));
+    }
+
+    #[test]
+    fn fit_scaling_fills_the_window() {
+        assert_eq!(Viewport::fit(64, 32, 700.0, 400.0, ScaleMode::Fit), viewport(0.0, 25.0, 10.9375, 10.9375));
+        assert_eq!(Viewport::fit(64, 32, 640.0, 640.0, ScaleMode::Fit), viewport(0.0, 160.0, 10.0, 10.0));
+    }
+
+    #[test]
+    fn integer_scaling_falls_back_to_fit_in_small_windows() {
+        let small = Viewport::fit(64, 32, 50.0, 20.0, ScaleMode::Integer);
+        assert_eq!(small, Viewport::fit(64, 32, 50.0, 20.0, ScaleMode::Fit));
+        assert_eq!(small, viewport(5.0, 0.0, 0.625, 0.625));
+    }
+
+    #[test]
+    fn vip_hires_pixels_are_stretched_to_2_by_1() {
+        for &mode in [ScaleMode::Integer, ScaleMode::Fit].iter() {
+            assert_eq!(Viewport::fit(64, 64, 640.0, 320.0, mode), viewport(0.0, 0.0, 10.0, 5.0));
+        }
+        assert_eq!(Viewport::fit(64, 64, 700.0, 400.0, ScaleMode::Integer), viewport(30.0, 40.0, 10.0, 5.0));
+    }
+}